@@ -0,0 +1,240 @@
+//! Dictionary tokens extracted from the target itself.
+//!
+//! Magic values and keywords of the input format are mostly compiled into the parser: as
+//! strings in `.rodata`, or as immediates of the comparisons in `.text` that check them.
+//! Both are collected from the target binary; the libraries it links (other than the C and
+//! C++ runtime) contribute their strings. Libraries are only mapped once the target ran to
+//! `start_pc`, so the extraction has to wait for [`crate::harness::Harness::init`].
+use std::{collections::HashSet, fs, io::Write, path::Path};
+
+use libafl::Error;
+use libafl_qemu::{elf::EasyElf, Qemu};
+
+use crate::modules::alloc_fail::host_path;
+
+const MIN_STRING: usize = 4;
+
+/// Longer strings are messages rather than keywords
+const MAX_STRING: usize = 32;
+
+/// A big library shouldn't drown the tokens of the target
+const MAX_PER_OBJECT: usize = 1024;
+
+/// Runtime libraries every target links, their strings say nothing about the input
+const RUNTIME_LIBS: [&str; 9] = [
+    "ld-", "libc.so", "libc-", "libm.so", "libpthread", "libdl", "librt", "libstdc++", "libgcc_s",
+];
+
+#[derive(Debug, Default)]
+pub struct AutoDict {
+    tokens: Vec<Vec<u8>>,
+    seen: HashSet<Vec<u8>>,
+}
+
+impl AutoDict {
+    /// Scan the target and the libraries QEMU mapped for it
+    pub fn extract(qemu: Qemu, rootfs: Option<&Path>) -> Self {
+        let mut dict = Self::default();
+
+        let binary = Path::new(qemu.binary_path());
+        if let Err(e) = dict.add_object(binary, true) {
+            log::warn!("Failed to extract tokens from {binary:?}: {e}");
+        }
+
+        let mut libs = Vec::new();
+        for map in qemu.mappings() {
+            let Some(path) = map.path() else {
+                continue;
+            };
+            let name = Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !name.contains(".so") || RUNTIME_LIBS.iter().any(|lib| name.starts_with(lib)) {
+                continue;
+            }
+            if let Some(lib) = host_path(path, rootfs) {
+                if lib != binary && !libs.contains(&lib) {
+                    libs.push(lib);
+                }
+            }
+        }
+        for lib in libs {
+            if let Err(e) = dict.add_object(&lib, false) {
+                log::warn!("Failed to extract tokens from {lib:?}: {e}");
+            }
+        }
+
+        log::info!("Extracted {} tokens from the target", dict.tokens.len());
+        dict
+    }
+
+    pub fn tokens(&self) -> &[Vec<u8>] {
+        &self.tokens
+    }
+
+    fn add(&mut self, token: Vec<u8>) -> bool {
+        if self.seen.insert(token.clone()) {
+            self.tokens.push(token);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Strings of `.rodata` and, for the target itself, compare immediates of `.text`
+    fn add_object(&mut self, path: &Path, code: bool) -> Result<(), Error> {
+        let mut elf_buffer = Vec::new();
+        let (rodata, text) = {
+            let elf = EasyElf::from_file(path, &mut elf_buffer)?;
+            let goblin = elf.goblin();
+            let mut rodata = Vec::new();
+            let mut text = None;
+            for section in &goblin.section_headers {
+                let name = goblin.shdr_strtab.get_at(section.sh_name).unwrap_or_default();
+                let range = section.sh_offset as usize..(section.sh_offset + section.sh_size) as usize;
+                if name == ".rodata" || name.starts_with(".rodata.") {
+                    rodata.push(range);
+                } else if name == ".text" {
+                    text = Some((range, section.sh_addr));
+                }
+            }
+            (rodata, text)
+        };
+
+        // Immediates first, they are fewer and more often magic values than strings are
+        let mut found = Vec::new();
+        if let Some((range, addr)) = text.filter(|_| code) {
+            if let Some(data) = elf_buffer.get(range) {
+                found.extend(compare_immediates(data, addr));
+            }
+        }
+        for range in rodata {
+            if let Some(data) = elf_buffer.get(range) {
+                found.extend(strings(data).map(<[u8]>::to_vec));
+            }
+        }
+
+        let mut added = 0;
+        for token in found {
+            if added >= MAX_PER_OBJECT {
+                break;
+            }
+            if self.add(token) {
+                added += 1;
+            }
+        }
+        log::debug!("{added} tokens from {path:?}");
+        Ok(())
+    }
+
+    /// Write the tokens in the format of `-x`, so a later run can load them without scanning
+    pub fn dump(&self, path: &Path) -> Result<(), Error> {
+        let mut file = fs::File::create(path)?;
+        for (idx, token) in self.tokens.iter().enumerate() {
+            let mut escaped = String::new();
+            for byte in token {
+                match byte {
+                    b'"' | b'\\' => escaped.push_str(&format!("\\x{byte:02x}")),
+                    0x20..=0x7e => escaped.push(char::from(*byte)),
+                    _ => escaped.push_str(&format!("\\x{byte:02x}")),
+                }
+            }
+            writeln!(file, "auto_{idx:04}=\"{escaped}\"")?;
+        }
+        log::info!("Dumped {} tokens to {path:?}", self.tokens.len());
+        Ok(())
+    }
+}
+
+/// NUL or otherwise terminated runs of printable characters, without format strings
+fn strings(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(|byte| !(0x20..=0x7e).contains(byte))
+        .filter(|string| (MIN_STRING..=MAX_STRING).contains(&string.len()))
+        .filter(|string| !string.contains(&b'%'))
+}
+
+/// The bytes of a compare operand, in both byte orders. Values that fit in a byte (also
+/// when sign extended) are found by havoc alone.
+pub(crate) fn immediate_tokens(value: i64, size: usize) -> Option<[Vec<u8>; 2]> {
+    if value.unsigned_abs() < 0x100 || !(2..=8).contains(&size) {
+        return None;
+    }
+    let le = value.to_le_bytes()[..size].to_vec();
+    let mut be = le.clone();
+    be.reverse();
+    Some([le, be])
+}
+
+/// The next instructions may start after data in `.text`, disassemble from there
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+fn compare_immediates(code: &[u8], addr: u64) -> Vec<Vec<u8>> {
+    use capstone::prelude::*;
+
+    const BATCH: usize = 4096;
+    #[cfg(feature = "x86_64")]
+    const STEP: usize = 1;
+    #[cfg(feature = "aarch64")]
+    const STEP: usize = 4;
+
+    let Some(cs) = crate::modules::crash_info::disassembler() else {
+        return Vec::new();
+    };
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < code.len() {
+        let Ok(insns) = cs.disasm_count(&code[pos..], addr + pos as u64, BATCH) else {
+            break;
+        };
+        let Some(last) = insns.iter().last() else {
+            pos += STEP;
+            continue;
+        };
+        pos = (last.address() - addr) as usize + last.len();
+
+        for insn in insns.iter() {
+            #[cfg(feature = "x86_64")]
+            let compare = matches!(insn.mnemonic(), Some("cmp" | "test"));
+            #[cfg(feature = "aarch64")]
+            let compare = matches!(insn.mnemonic(), Some("cmp" | "cmn" | "ccmp" | "ccmn"));
+            if !compare {
+                continue;
+            }
+            let Ok(detail) = cs.insn_detail(&insn) else {
+                continue;
+            };
+            for op in detail.arch_detail().operands() {
+                #[cfg(feature = "x86_64")]
+                if let arch::ArchOperand::X86Operand(arch::x86::X86Operand {
+                    op_type: arch::x86::X86OperandType::Imm(value),
+                    size,
+                    ..
+                }) = op
+                {
+                    tokens.extend(immediate_tokens(value, size as usize).into_iter().flatten());
+                }
+
+                // The 12-bit immediates of aarch64 compares may be shifted into a 24-bit value
+                #[cfg(feature = "aarch64")]
+                if let arch::ArchOperand::Arm64Operand(arch::arm64::Arm64Operand {
+                    op_type: arch::arm64::Arm64OperandType::Imm(value),
+                    shift,
+                    ..
+                }) = op
+                {
+                    let value = match shift {
+                        arch::arm64::Arm64Shift::Lsl(bits) => value << bits,
+                        _ => value,
+                    };
+                    tokens.extend(immediate_tokens(value, 4).into_iter().flatten());
+                }
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+fn compare_immediates(_code: &[u8], _addr: u64) -> Vec<Vec<u8>> {
+    Vec::new()
+}
@@ -12,12 +12,13 @@ use libafl_bolts::{rands::StdRand, tuples::tuple_list};
 #[cfg(feature = "injections")]
 use libafl_qemu::modules::injections::InjectionModule;
 use libafl_qemu::modules::{
-    asan::{AsanModule, QemuAsanOptions}, asan_guest::AsanGuestModule, cmplog::CmpLogModule, utils::filters::StdAddressFilter, DrCovModule, InjectionModule
+    asan::{AsanModule, QemuAsanOptions}, asan_guest::AsanGuestModule, cmplog::{CmpLogModule, CmpLogRoutinesModule}, utils::filters::StdAddressFilter, DrCovModule, InjectionModule
 };
 
 use crate::{
     harness::Harness,
     instance::{ClientMgr, Instance},
+    modules::ExecTraceModule,
     options::FuzzerOptions,
 };
 
@@ -114,6 +115,34 @@ impl Client<'_> {
             .mgr(mgr)
             .client_description(client_description);
 
+        let options = self.options;
+        // Cmplog cores also log the operands of `memcmp`-like calls, but only for `--redqueen`
+        macro_rules! run_cmplog {
+            ($($module:expr),* $(,)?) => {
+                if options.redqueen {
+                    instance_builder.build().run(
+                        args,
+                        tuple_list!(
+                            CmpLogModule::default(),
+                            CmpLogRoutinesModule::new(StdAddressFilter::default()),
+                            $($module),*
+                        ),
+                        state,
+                        options,
+                        core_id,
+                    )
+                } else {
+                    instance_builder.build().run(
+                        args,
+                        tuple_list!(CmpLogModule::default(), $($module),*),
+                        state,
+                        options,
+                        core_id,
+                    )
+                }
+            };
+        }
+
         if self.options.rerun_input.is_some() && self.options.drcov.is_some() {
             // Special code path for re-running inputs with DrCov.
             // TODO: Add ASan support, injection support
@@ -125,49 +154,27 @@ impl Client<'_> {
             instance_builder
                 .build()
                 .run(args, tuple_list!(drcov), state, self.options, core_id)
+        } else if let (Some(_), Some(trace)) = (&self.options.rerun_input, &self.options.trace) {
+            let trace = ExecTraceModule::new(
+                trace,
+                self.options.trace_format,
+                self.options.trace_mem,
+                self.options.trace_syscalls,
+            )?;
+            instance_builder
+                .build()
+                .run(args, tuple_list!(trace), state, self.options, core_id)
         } else if is_asan && is_cmplog {
             if let Some(injection_module) = injection_module {
-                instance_builder.build().run(
-                    args,
-                    tuple_list!(
-                        CmpLogModule::default(),
-                        AsanModule::default(&env),
-                        injection_module,
-                    ),
-                    state,
-                    self.options,
-                    core_id,
-                )
+                run_cmplog!(AsanModule::default(&env), injection_module)
             } else {
-                instance_builder.build().run(
-                    args,
-                    tuple_list!(CmpLogModule::default(), AsanModule::default(&env),),
-                    state,
-                    self.options,
-                    core_id,
-                )
+                run_cmplog!(AsanModule::default(&env))
             }
         } else if is_asan_guest && is_cmplog {
             if let Some(injection_module) = injection_module {
-                instance_builder.build().run(
-                    args,
-                    tuple_list!(
-                        CmpLogModule::default(),
-                        AsanGuestModule::default(&env),
-                        injection_module
-                    ),
-                    state,
-                    self.options,
-                    core_id,
-                )
+                run_cmplog!(AsanGuestModule::default(&env), injection_module)
             } else {
-                instance_builder.build().run(
-                    args,
-                    tuple_list!(CmpLogModule::default(), AsanGuestModule::default(&env),),
-                    state,
-                    self.options,
-                    core_id,
-                )
+                run_cmplog!(AsanGuestModule::default(&env))
             }
         } else if is_asan {
             if let Some(injection_module) = injection_module {
@@ -198,17 +205,9 @@ impl Client<'_> {
                 .run(args, tuple_list!(AsanGuestModule::default(&env)), state, self.options, core_id)
         } else if is_cmplog {
             if let Some(injection_module) = injection_module {
-                instance_builder.build().run(
-                    args,
-                    tuple_list!(CmpLogModule::default(), injection_module),
-                    state,
-                    self.options,
-                    core_id
-                )
+                run_cmplog!(injection_module)
             } else {
-                instance_builder
-                    .build()
-                    .run(args, tuple_list!(CmpLogModule::default()), state, self.options, core_id)
+                run_cmplog!()
             }
         } else if let Some(injection_module) = injection_module {
             instance_builder
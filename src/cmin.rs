@@ -0,0 +1,156 @@
+//! An `afl-cmin` like corpus minimizer reusing the edge map of the fuzzer
+use core::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    executors::{ExitKind, HasObservers},
+    fuzzer::ExecutesInput,
+    inputs::BytesInput,
+    observers::{MapObserver, TimeObserver},
+    Error,
+};
+use libafl_bolts::{
+    hash_std,
+    tuples::{Handle, MatchName},
+};
+
+use crate::options::FuzzerOptions;
+
+/// A single input loaded from one of the queues
+struct CminEntry {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    exec_time: Duration,
+    /// (edge index, hitcount bucket) tuples covered by this input, like `afl-showmap` reports them
+    tuples: Vec<(usize, u8)>,
+}
+
+pub struct CorpusMinimizer<C> {
+    map_observer: Handle<C>,
+    time_observer: Handle<TimeObserver>,
+}
+
+impl<C> CorpusMinimizer<C> {
+    pub fn new(map_observer: Handle<C>, time_observer: Handle<TimeObserver>) -> Self {
+        Self {
+            map_observer,
+            time_observer,
+        }
+    }
+
+    /// Execute every file of `dirs` once and write a subset that keeps the total
+    /// edge coverage to `out_dir`, preferring small and fast inputs.
+    pub fn minimize<E, EM, O, S, Z>(
+        &self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        mgr: &mut EM,
+        dirs: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<(), Error>
+    where
+        C: AsRef<O>,
+        O: MapObserver<Entry = u8>,
+        E: HasObservers,
+        E::Observers: MatchName,
+        Z: ExecutesInput<E, EM, BytesInput, S>,
+    {
+        let files = FuzzerOptions::corpus_files(dirs)?;
+        log::info!("cmin: loaded {} files from {dirs:?}", files.len());
+
+        let mut seen = HashSet::new();
+        let mut entries = Vec::with_capacity(files.len());
+
+        for path in files {
+            let bytes = fs::read(&path)?;
+            if !seen.insert(hash_std(&bytes)) {
+                log::debug!("cmin: skipping duplicate {path:?}");
+                continue;
+            }
+
+            let input = BytesInput::new(bytes.clone());
+            let exit_kind = fuzzer.execute_input(state, executor, mgr, &input)?;
+            if exit_kind != ExitKind::Ok {
+                log::warn!("cmin: {path:?} exited with {exit_kind:?}, skipping");
+                continue;
+            }
+
+            let observers = executor.observers();
+            let map = observers[&self.map_observer].as_ref();
+            let initial = map.initial();
+            let tuples = (0..map.usable_count())
+                .filter_map(|idx| {
+                    let value = map.get(idx);
+                    (value != initial).then_some((idx, value))
+                })
+                .collect::<Vec<(usize, u8)>>();
+            let exec_time = observers[&self.time_observer]
+                .last_runtime()
+                .unwrap_or_default();
+
+            entries.push(CminEntry {
+                path,
+                bytes,
+                exec_time,
+                tuples,
+            });
+        }
+
+        // Smaller inputs first, execution time breaks ties
+        entries.sort_by_key(|e| (e.bytes.len(), e.exec_time));
+
+        // The first (i.e. best) entry covering each tuple
+        let mut best = HashMap::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            for tuple in &entry.tuples {
+                best.entry(*tuple).or_insert(idx);
+            }
+        }
+
+        let mut tuples = best.keys().copied().collect::<Vec<(usize, u8)>>();
+        tuples.sort_unstable();
+
+        let mut covered = HashSet::new();
+        let mut keep = Vec::new();
+        for tuple in tuples {
+            if covered.contains(&tuple) {
+                continue;
+            }
+            let idx = best[&tuple];
+            covered.extend(entries[idx].tuples.iter().copied());
+            keep.push(idx);
+        }
+
+        fs::create_dir_all(out_dir)?;
+        for idx in &keep {
+            let entry = &entries[*idx];
+            let name = entry
+                .path
+                .file_name()
+                .ok_or_else(|| Error::illegal_argument(format!("Invalid file {:?}", entry.path)))?;
+            let mut dst = out_dir.join(name);
+            if dst.exists() {
+                dst = out_dir.join(format!("{:016x}", hash_std(&entry.bytes)));
+            }
+            fs::write(&dst, &entry.bytes)?;
+        }
+
+        log::info!(
+            "cmin: kept {} of {} inputs covering {} tuples in {out_dir:?}",
+            keep.len(),
+            entries.len(),
+            covered.len()
+        );
+        println!(
+            "Narrowed down to {} of {} inputs, written to {out_dir:?}",
+            keep.len(),
+            entries.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+
+use libafl::{
+    corpus::Testcase,
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    Error, HasMetadata,
+};
+use libafl_bolts::Named;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::cmp_coverage::{CmpCoverageMeta, CMP_MAP_SIZE};
+
+/// The most equal operand bits seen so far, per comparison site
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CmpCoverageBestMeta {
+    best: Vec<u8>,
+}
+
+libafl_bolts::impl_serdeany!(CmpCoverageBestMeta);
+
+/// Rewards inputs that make more operand bits of a comparison equal than any input before,
+/// see `CmpCoverageModule`. Without an enabled module there is nothing to reward.
+/// The best values are only raised for inputs that are added, in `append_metadata`.
+pub struct CmpCoverageFeedback;
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for CmpCoverageFeedback
+where
+    S: HasMetadata,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let Ok(trace) = _state.metadata::<CmpCoverageMeta>() else {
+            return Ok(false);
+        };
+        let best = _state
+            .metadata::<CmpCoverageBestMeta>()
+            .map_or(&[][..], |coverage| &coverage.best[..]);
+        Ok(trace.sites.iter().any(|(slot, equal_bits)| {
+            *equal_bits > best.get(*slot as usize).copied().unwrap_or(0)
+        }))
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        _testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let Ok(trace) = _state.metadata::<CmpCoverageMeta>() else {
+            return Ok(());
+        };
+        let sites = trace.sites.clone();
+
+        let coverage = _state.metadata_or_insert_with(CmpCoverageBestMeta::default);
+        coverage.best.resize(CMP_MAP_SIZE, 0);
+        for (slot, equal_bits) in sites {
+            let best = &mut coverage.best[slot as usize];
+            *best = (*best).max(equal_bits);
+        }
+        Ok(())
+    }
+}
+
+impl<S> StateInitializer<S> for CmpCoverageFeedback {}
+
+impl Named for CmpCoverageFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("CmpCoverageFeedback");
+        &NAME
+    }
+}
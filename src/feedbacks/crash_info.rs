@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+
+use libafl::{
+    corpus::Testcase,
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    inputs::{BytesInput, HasTargetBytes},
+    Error, HasMetadata,
+};
+use libafl_bolts::{AsSlice, Named};
+
+use crate::modules::{checksum_patch::UnpatchedCrashesMeta, crash_info::CrashInfo};
+
+/// Attaches the [`CrashInfo`] of the guest crash (signal, PC, fault address, registers) to the
+/// solution, so it ends up as JSON in the `.<name>.metadata` file next to the crash in
+/// `crashes/`.
+/// A crash that only got past checksum checks because of `--checksum-patch` isn't stored: its
+/// checksums are fixed and it is queued for the `VerifyCrashesStage`, which re-executes it
+/// against the original checks.
+pub struct CrashInfoFeedback;
+
+impl<EM, OT, S> Feedback<EM, BytesInput, OT, S> for CrashInfoFeedback
+where
+    S: HasMetadata,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &BytesInput,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let Some(crash_info) = CrashInfo::peek().filter(|info| !info.checksum_patches.is_empty())
+        else {
+            return Ok(true);
+        };
+        let mut bytes = _input.target_bytes().as_slice().to_vec();
+        for hit in &crash_info.checksum_patches {
+            hit.fix(&mut bytes);
+        }
+        if _state
+            .metadata_or_insert_with(UnpatchedCrashesMeta::default)
+            .push_pending(bytes, crash_info.checksum_patches)
+        {
+            log::info!(
+                "Crash @ {} went through patched checksum checks, queued for verification",
+                crash_info.pc
+            );
+        }
+        Ok(false)
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        _testcase: &mut Testcase<BytesInput>,
+    ) -> Result<(), Error> {
+        if let Some(mut crash_info) = CrashInfo::take() {
+            log::info!(
+                "Guest crashed with {} @ {}",
+                crash_info.signal_name,
+                crash_info.pc
+            );
+            // The checks the crash got past while fuzzing, before its checksums were fixed
+            if let Some(hits) = _state
+                .metadata_mut::<UnpatchedCrashesMeta>()
+                .ok()
+                .and_then(UnpatchedCrashesMeta::take_verifying)
+            {
+                crash_info.checksum_patches = hits;
+            }
+            _testcase.add_metadata(crash_info);
+        }
+        Ok(())
+    }
+}
+
+impl<S> StateInitializer<S> for CrashInfoFeedback {}
+
+impl Named for CrashInfoFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("CrashInfoFeedback");
+        &NAME
+    }
+}
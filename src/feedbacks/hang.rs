@@ -0,0 +1,117 @@
+use std::{
+    borrow::Cow,
+    collections::{HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+};
+
+use libafl::{
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    inputs::HasTargetBytes,
+    Error, HasMetadata,
+};
+use libafl_bolts::{hash_std, AsSlice, Named};
+use libafl_qemu::GuestAddr;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::hang_pc::last_block;
+
+/// Timed out inputs are kept at most this many at a time until they are verified
+const MAX_PENDING_HANGS: usize = 16;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HangsMeta {
+    /// The blocks the stored hangs were stuck in
+    known_pcs: HashSet<GuestAddr>,
+    /// Timed out inputs waiting to be re-executed with the longer timeout
+    pending: VecDeque<Vec<u8>>,
+    /// Set while a pending input is re-executed by the `VerifyHangsStage`
+    verifying: bool,
+}
+
+impl HangsMeta {
+    pub fn pop_pending(&mut self) -> Option<Vec<u8>> {
+        self.pending.pop_front()
+    }
+
+    pub fn is_verifying(&self) -> bool {
+        self.verifying
+    }
+
+    pub fn set_verifying(&mut self, verifying: bool) {
+        self.verifying = verifying;
+    }
+}
+
+libafl_bolts::impl_serdeany!(HangsMeta);
+
+/// Stores hangs in their own directory instead of the solutions.
+///
+/// A timeout is only queued at first, the process is restarted by the timeout handler anyways.
+/// The `VerifyHangsStage` then re-executes it with the longer hang timeout, and only if it times
+/// out again it is written to `hangs/`, once per block the execution was stuck in (see
+/// `HangPcModule`, the PC register is stale in the timeout handler).
+pub struct HangFeedback {
+    hangs_dir: PathBuf,
+}
+
+impl HangFeedback {
+    pub fn new(hangs_dir: PathBuf) -> Self {
+        Self { hangs_dir }
+    }
+}
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for HangFeedback
+where
+    S: HasMetadata,
+    I: HasTargetBytes,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        if *_exit_kind != ExitKind::Timeout {
+            // The verification didn't time out again, e.g. it crashed
+            if let Ok(meta) = _state.metadata_mut::<HangsMeta>() {
+                meta.verifying = false;
+            }
+            return Ok(false);
+        }
+
+        let bytes = _input.target_bytes().as_slice().to_vec();
+        let meta = _state.metadata_or_insert_with(HangsMeta::default);
+
+        if meta.verifying {
+            meta.verifying = false;
+            let pc = last_block().unwrap_or_default();
+            if meta.known_pcs.insert(pc) {
+                fs::create_dir_all(&self.hangs_dir)?;
+                let name = format!("{pc:#x}-{:016x}", hash_std(&bytes));
+                fs::write(self.hangs_dir.join(&name), &bytes)?;
+                log::info!("Verified hang {name}");
+            } else {
+                log::debug!("Verified hang stuck at known block {pc:#x}");
+            }
+        } else if meta.pending.len() < MAX_PENDING_HANGS && !meta.pending.contains(&bytes) {
+            log::debug!("Timeout, queued for verification");
+            meta.pending.push_back(bytes);
+        }
+
+        // Hangs never end up in the solutions
+        Ok(false)
+    }
+}
+
+impl<S> StateInitializer<S> for HangFeedback {}
+
+impl Named for HangFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("HangFeedback");
+        &NAME
+    }
+}
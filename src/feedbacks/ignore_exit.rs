@@ -3,7 +3,7 @@ use std::borrow::Cow;
 use libafl::{executors::ExitKind, feedbacks::{Feedback, StateInitializer}, Error, HasMetadata};
 use libafl_bolts::Named;
 
-use crate::modules::ExecMeta;
+use crate::{modules::ExecMeta, trace::exec_event};
 
 pub struct IgnoreExitFeedback;
 
@@ -24,11 +24,11 @@ where
             .get_mut::<ExecMeta>()
             .expect("Can't get exec_meta");
         if exec_meta.ignore {
-            log::info!("IgnoreExitFeedback: ignoring exit");
+            exec_event!("feedback", "ignored exit");
+            log::trace!(target: "feedback", "IgnoreExitFeedback: ignoring exit");
             exec_meta.ignore = false;
             Ok(false)
         } else {
-            log::info!("IgnoreExitFeedback: No exiting found");
             Ok(true)
         }
     }
@@ -0,0 +1,128 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use libafl::{
+    corpus::Testcase,
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    inputs::HasTargetBytes,
+    Error, HasMetadata,
+};
+use libafl_bolts::{hash_std, AsSlice, Named};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    modules::crash_info::{CrashInfo, CrashSignature},
+    options::FuzzerOptions,
+};
+
+/// Content hashes of all crashes stored so far, and the signatures of those of a resumed run
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownCrashesMeta {
+    hashes: HashSet<u64>,
+    /// Mutated inputs hardly ever hash like a stored crash, but crash where it did
+    signatures: HashSet<CrashSignature>,
+}
+
+impl KnownCrashesMeta {
+    /// Hash all crashes found in `dirs`, and read their signatures from their metadata files
+    pub fn load(dirs: &[PathBuf]) -> Result<Self, Error> {
+        let mut known = Self::default();
+        for file in FuzzerOptions::corpus_files(dirs)? {
+            known.hashes.insert(hash_std(&fs::read(&file)?));
+            if let Some(signature) = stored_signature(&file) {
+                known.signatures.insert(signature);
+            }
+        }
+        Ok(known)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn signatures(&self) -> usize {
+        self.signatures.len()
+    }
+}
+
+libafl_bolts::impl_serdeany!(KnownCrashesMeta);
+
+/// The signature of the `CrashInfo` in the `.<name>.metadata` file next to a stored crash
+fn stored_signature(file: &Path) -> Option<CrashSignature> {
+    let name = file.file_name()?.to_string_lossy();
+    let metadata = fs::read(file.with_file_name(format!(".{name}.metadata"))).ok()?;
+    let value = serde_json::from_slice::<Value>(&metadata).ok()?;
+    find_crash_info(&value)?.signature()
+}
+
+/// The layout of the metadata map is up to LibAFL, look for anything shaped like a `CrashInfo`
+fn find_crash_info(value: &Value) -> Option<CrashInfo> {
+    match value {
+        Value::Object(map) if map.contains_key("signal") && map.contains_key("pc") => {
+            serde_json::from_value(value.clone()).ok()
+        }
+        Value::Object(map) => map.values().find_map(find_crash_info),
+        Value::Array(values) => values.iter().find_map(find_crash_info),
+        _ => None,
+    }
+}
+
+/// Rejects solutions we already stored, so a resumed campaign doesn't report old crashes
+/// again: the same input, or a crash with the signature of a crash of the resumed run
+pub struct KnownCrashFeedback;
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for KnownCrashFeedback
+where
+    S: HasMetadata,
+    I: HasTargetBytes,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let Ok(known) = _state.metadata::<KnownCrashesMeta>() else {
+            return Ok(true);
+        };
+        if known.hashes.contains(&hash_std(_input.target_bytes().as_slice())) {
+            return Ok(false);
+        }
+        let signature = CrashInfo::peek().and_then(|info| info.signature());
+        Ok(signature.map_or(true, |signature| !known.signatures.contains(&signature)))
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        _testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        if let Some(input) = _testcase.input() {
+            let hash = hash_std(input.target_bytes().as_slice());
+            _state
+                .metadata_or_insert_with(KnownCrashesMeta::default)
+                .hashes
+                .insert(hash);
+        }
+        Ok(())
+    }
+}
+
+impl<S> StateInitializer<S> for KnownCrashFeedback {}
+
+impl Named for KnownCrashFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("KnownCrashFeedback");
+        &NAME
+    }
+}
@@ -1 +1,6 @@
-pub mod ignore_exit;
\ No newline at end of file
+pub mod cmp_coverage;
+pub mod crash_info;
+pub mod hang;
+pub mod ignore_exit;
+pub mod known_crash;
+pub mod syscall_coverage;
\ No newline at end of file
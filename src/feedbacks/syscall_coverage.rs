@@ -0,0 +1,81 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use libafl::{
+    corpus::Testcase,
+    executors::ExitKind,
+    feedbacks::{Feedback, StateInitializer},
+    Error, HasMetadata,
+};
+use libafl_bolts::Named;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::syscall_trace::{SyscallKey, SyscallTraceMeta};
+
+/// All syscall / argument combinations seen so far
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyscallCoverageMeta {
+    seen: HashSet<SyscallKey>,
+}
+
+libafl_bolts::impl_serdeany!(SyscallCoverageMeta);
+
+/// Rewards inputs that trigger a new syscall / argument combination, see `SyscallRecord::key`.
+/// Without an enabled `SyscallTraceModule` there is nothing to reward.
+///
+/// Like the map feedbacks, the combinations only count as seen once an input with them is
+/// added, in `append_metadata`.
+pub struct SyscallCoverageFeedback;
+
+impl SyscallCoverageFeedback {
+    /// The combinations of the last execution
+    fn keys<S: HasMetadata>(state: &S) -> Vec<SyscallKey> {
+        state
+            .metadata::<SyscallTraceMeta>()
+            .map(|trace| trace.records.iter().map(|record| record.key()).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<EM, I, OT, S> Feedback<EM, I, OT, S> for SyscallCoverageFeedback
+where
+    S: HasMetadata,
+{
+    fn is_interesting(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _input: &I,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error> {
+        let keys = Self::keys(_state);
+        let Ok(coverage) = _state.metadata::<SyscallCoverageMeta>() else {
+            return Ok(!keys.is_empty());
+        };
+        Ok(keys.iter().any(|key| !coverage.seen.contains(key)))
+    }
+
+    fn append_metadata(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        _observers: &OT,
+        _testcase: &mut Testcase<I>,
+    ) -> Result<(), Error> {
+        let keys = Self::keys(_state);
+        _state
+            .metadata_or_insert_with(SyscallCoverageMeta::default)
+            .seen
+            .extend(keys);
+        Ok(())
+    }
+}
+
+impl<S> StateInitializer<S> for SyscallCoverageFeedback {}
+
+impl Named for SyscallCoverageFeedback {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("SyscallCoverageFeedback");
+        &NAME
+    }
+}
@@ -0,0 +1,318 @@
+//! Checksums that formats store next to their data, and the `--checksums` rules that
+//! recompute them in any input after it was mutated.
+use std::{fs, path::Path};
+
+use libafl::Error;
+use serde::Deserialize;
+
+use crate::format::spec::Endian;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// CRC-32 as used by PNG, zip and gzip
+    Crc32,
+    /// As used by zlib
+    Adler32,
+    /// Sum of all bytes, truncated to the field size
+    Sum8,
+    /// Xor of all bytes
+    Xor8,
+    /// Any other CRC, e.g. `{ crc = { width = 16, poly = 0x1021, init = 0xffff } }`
+    Crc(CrcParams),
+}
+
+/// A CRC in the parameter model of the "Catalogue of parametrised CRC algorithms"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrcParams {
+    /// Bits, 8 to 64
+    pub width: u32,
+    pub poly: u64,
+    #[serde(default)]
+    pub init: u64,
+    /// Input and output reflected, as most CRCs are
+    #[serde(default)]
+    pub reflect: bool,
+    #[serde(default)]
+    pub xor_out: u64,
+}
+
+impl Algorithm {
+    pub fn compute(self, data: &[u8]) -> u64 {
+        match self {
+            Self::Crc32 => u64::from(crc32(data)),
+            Self::Adler32 => u64::from(adler32(data)),
+            Self::Sum8 => data.iter().fold(0_u64, |sum, b| sum.wrapping_add(u64::from(*b))),
+            Self::Xor8 => u64::from(data.iter().fold(0_u8, |xor, b| xor ^ b)),
+            Self::Crc(params) => crc(params, data),
+        }
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0_u32, |crc, b| {
+        CRC32_TABLE[((crc ^ u32::from(*b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn reflect(value: u64, width: u32) -> u64 {
+    value.reverse_bits() >> (64 - width)
+}
+
+/// Bitwise, custom CRCs are rare enough to not need a table
+fn crc(params: CrcParams, data: &[u8]) -> u64 {
+    let width = params.width.clamp(8, 64);
+    let mask = u64::MAX >> (64 - width);
+    let poly = params.poly & mask;
+
+    let crc = if params.reflect {
+        let poly = reflect(poly, width);
+        data.iter().fold(reflect(params.init & mask, width), |mut crc, b| {
+            crc ^= u64::from(*b);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            }
+            crc
+        })
+    } else {
+        let top = 1 << (width - 1);
+        data.iter().fold(params.init & mask, |mut crc, b| {
+            crc ^= u64::from(*b) << (width - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 { ((crc << 1) ^ poly) & mask } else { (crc << 1) & mask };
+            }
+            crc
+        })
+    };
+    (crc ^ params.xor_out) & mask
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1_u32, 0_u32);
+    // Sums of up to 5552 bytes can't overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Recomputes one checksum of the input. Offsets may be negative to count from the end.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChecksumRule {
+    pub algorithm: Algorithm,
+    /// First checksummed byte
+    #[serde(default)]
+    pub start: i64,
+    /// After the last checksummed byte, the end of the input by default
+    pub end: Option<i64>,
+    /// Where the checksum is stored
+    pub at: i64,
+    pub size: usize,
+    #[serde(default)]
+    pub endian: Endian,
+}
+
+impl ChecksumRule {
+    fn resolve(offset: i64, len: usize) -> Option<usize> {
+        let offset = if offset < 0 {
+            len.checked_sub(offset.unsigned_abs() as usize)?
+        } else {
+            offset as usize
+        };
+        (offset <= len).then_some(offset)
+    }
+
+    /// Rules that don't fit the input are skipped
+    fn apply(&self, data: &mut [u8]) {
+        let len = data.len();
+        let (Some(start), Some(end), Some(at)) = (
+            Self::resolve(self.start, len),
+            Self::resolve(self.end.unwrap_or(len as i64), len),
+            Self::resolve(self.at, len),
+        ) else {
+            return;
+        };
+        if start > end || at + self.size > len {
+            return;
+        }
+        let checksum = self.algorithm.compute(&data[start..end]);
+        self.endian.write(&mut data[at..at + self.size], checksum);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rule: Vec<ChecksumRule>,
+}
+
+/// The `--checksums` rules, applied in order so later ones may cover earlier checksums
+#[derive(Debug, Default)]
+pub struct ChecksumRules {
+    rules: Vec<ChecksumRule>,
+}
+
+impl ChecksumRules {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let source = fs::read_to_string(path)?;
+        let file: RulesFile = toml::from_str(&source)
+            .map_err(|e| Error::illegal_argument(format!("Invalid checksum rules {path:?}: {e}")))?;
+        for rule in &file.rule {
+            let width = match rule.algorithm {
+                Algorithm::Crc(params) => params.width,
+                _ => 8,
+            };
+            if !(1..=8).contains(&rule.size) || !(8..=64).contains(&width) {
+                return Err(Error::illegal_argument(format!(
+                    "Invalid checksum rule in {path:?}: sizes are 1 to 8 bytes, CRC widths 8 to 64 bits"
+                )));
+            }
+        }
+        Ok(Self { rules: file.rule })
+    }
+
+    pub fn apply(&self, data: &mut [u8]) {
+        for rule in &self.rules {
+            rule.apply(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The input of the check values in the CRC catalogue
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(Algorithm::Crc32.compute(CHECK), 0xcbf4_3926);
+        assert_eq!(Algorithm::Crc32.compute(b""), 0);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(Algorithm::Adler32.compute(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(Algorithm::Adler32.compute(b""), 1);
+        // Past the 5552 bytes summed before each modulo
+        assert_eq!(Algorithm::Adler32.compute(&[0xff; 6000]), 0xa497_59ea);
+    }
+
+    #[test]
+    fn sum_and_xor() {
+        assert_eq!(Algorithm::Sum8.compute(&[0xff, 0x02]), 0x101);
+        assert_eq!(Algorithm::Xor8.compute(&[0xf0, 0x0f, 0x01]), 0xfe);
+    }
+
+    #[test]
+    fn crc_catalogue_check_values() {
+        // (width, poly, init, reflect, xor_out, check)
+        let catalogue = [
+            // CRC-8/SMBUS
+            (8, 0x07, 0, false, 0, 0xf4),
+            // CRC-16/IBM-3740, better known as CCITT-FALSE
+            (16, 0x1021, 0xffff, false, 0, 0x29b1),
+            // CRC-16/ARC
+            (16, 0x8005, 0, true, 0, 0xbb3d),
+            // CRC-32/ISO-HDLC, the table driven `crc32`
+            (32, 0x04c1_1db7, 0xffff_ffff, true, 0xffff_ffff, 0xcbf4_3926),
+            // CRC-32/BZIP2
+            (32, 0x04c1_1db7, 0xffff_ffff, false, 0xffff_ffff, 0xfc89_1918),
+            // CRC-64/XZ
+            (64, 0x42f0_e1eb_a9ea_3693, u64::MAX, true, u64::MAX, 0x995d_c9bb_df19_39fa),
+        ];
+        for (width, poly, init, reflect, xor_out, check) in catalogue {
+            let params = CrcParams {
+                width,
+                poly,
+                init,
+                reflect,
+                xor_out,
+            };
+            assert_eq!(crc(params, CHECK), check, "{params:?}");
+        }
+    }
+
+    #[test]
+    fn rule_writes_checksum_from_the_end() {
+        let rule = ChecksumRule {
+            algorithm: Algorithm::Crc32,
+            start: 0,
+            end: Some(-4),
+            at: -4,
+            size: 4,
+            endian: Endian::Big,
+        };
+        let mut data = [CHECK, &[0; 4]].concat();
+        rule.apply(&mut data);
+        assert_eq!(&data[..9], CHECK);
+        assert_eq!(&data[9..], &[0xcb, 0xf4, 0x39, 0x26]);
+    }
+
+    #[test]
+    fn rule_truncates_to_the_field_size() {
+        let rule = ChecksumRule {
+            algorithm: Algorithm::Sum8,
+            start: 1,
+            end: None,
+            at: 0,
+            size: 1,
+            endian: Endian::Little,
+        };
+        let mut data = [0, 0x80, 0x90];
+        rule.apply(&mut data);
+        // 0x80 + 0x90 = 0x110
+        assert_eq!(data, [0x10, 0x80, 0x90]);
+    }
+
+    #[test]
+    fn rule_that_does_not_fit_is_skipped() {
+        let mut rule = ChecksumRule {
+            algorithm: Algorithm::Xor8,
+            start: 0,
+            end: None,
+            at: 3,
+            size: 2,
+            endian: Endian::Little,
+        };
+        let mut data = [1, 2, 3, 4];
+        rule.apply(&mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+
+        rule.at = 0;
+        rule.start = 3;
+        rule.end = Some(2);
+        rule.apply(&mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+
+        rule.start = -5;
+        rule.end = None;
+        rule.apply(&mut data);
+        assert_eq!(data, [1, 2, 3, 4]);
+    }
+}
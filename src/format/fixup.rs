@@ -0,0 +1,90 @@
+//! Post-processing of mutated inputs, so they get past the integrity checks of the target.
+use std::{borrow::Cow, rc::Rc};
+
+use libafl::{
+    corpus::CorpusId,
+    inputs::{BytesInput, HasTargetBytes},
+    mutators::{MutationResult, Mutator},
+    state::{HasMaxSize, HasRand},
+    Error,
+};
+use libafl_bolts::Named;
+
+use crate::{
+    format::{checksum::ChecksumRules, mutators::mutate_document, spec::FormatSpec},
+    modules::fault_schedule::FaultSchedule,
+};
+
+/// What gets fixed up after a mutation: the derived fields of `--format` and the
+/// `--checksums` rules, in this order
+#[derive(Debug, Default)]
+pub struct Fixups {
+    pub spec: Option<Rc<FormatSpec>>,
+    pub checksums: Option<ChecksumRules>,
+}
+
+impl Fixups {
+    fn apply<S>(&self, state: &mut S, input: &mut BytesInput) -> Result<(), Error>
+    where
+        S: HasRand + HasMaxSize,
+    {
+        if let Some(spec) = &self.spec {
+            // Lengths are trusted, so they stay as they are
+            mutate_document(spec, state, input, |_, _| Ok(MutationResult::Mutated))?;
+        }
+        if let Some(checksums) = &self.checksums {
+            let bytes = input.target_bytes();
+            let (data, schedule) = FaultSchedule::split(&bytes);
+            let mut fixed = data.to_vec();
+            checksums.apply(&mut fixed);
+            if data.len() != bytes.len() {
+                fixed = schedule.join(&fixed);
+            }
+            drop(bytes);
+            *input = BytesInput::new(fixed);
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the mutator of a stage: after each mutation, the input is parsed as the target
+/// would parse it and its offsets and checksums are recomputed. Without fixups, mutations
+/// pass through unchanged.
+#[derive(Debug)]
+pub struct FixupMutator<M> {
+    fixups: Rc<Fixups>,
+    inner: M,
+}
+
+impl<M> FixupMutator<M> {
+    pub fn new(fixups: Rc<Fixups>, inner: M) -> Self {
+        Self { fixups, inner }
+    }
+}
+
+impl<M, S> Mutator<BytesInput, S> for FixupMutator<M>
+where
+    M: Mutator<BytesInput, S>,
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        let result = self.inner.mutate(state, input)?;
+        if result == MutationResult::Mutated {
+            self.fixups.apply(state, input)?;
+        }
+        Ok(result)
+    }
+
+    fn post_exec(&mut self, state: &mut S, new_corpus_id: Option<CorpusId>) -> Result<(), Error> {
+        self.inner.post_exec(state, new_corpus_id)
+    }
+}
+
+impl<M> Named for FixupMutator<M>
+where
+    M: Named,
+{
+    fn name(&self) -> &Cow<'static, str> {
+        self.inner.name()
+    }
+}
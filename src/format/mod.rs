@@ -0,0 +1,23 @@
+//! Structure-aware fuzzing of binary formats described in a TOML spec.
+//!
+//! A spec lists the fields of the chunks a file is made of (see [`spec`]), e.g. for PNG:
+//! ```toml
+//! name = "png"
+//! endian = "big"
+//! magic = "89504e470d0a1a0a"
+//! chunk = [
+//!     { kind = "length", name = "length", size = 4, of = "data" },
+//!     { kind = "bytes", name = "type", size = 4 },
+//!     { kind = "blob", name = "data" },
+//!     { kind = "checksum", name = "crc", size = 4, algorithm = "crc32", over = ["type", "data"] },
+//! ]
+//! ```
+pub mod checksum;
+pub mod fixup;
+pub mod mutators;
+pub mod spec;
+
+pub use checksum::ChecksumRules;
+pub use fixup::{FixupMutator, Fixups};
+pub use mutators::format_mutations;
+pub use spec::FormatSpec;
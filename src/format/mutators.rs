@@ -0,0 +1,271 @@
+//! Mutations on the level of a [`FormatSpec`].
+use std::{borrow::Cow, iter, num::NonZeroUsize, rc::Rc};
+
+use libafl::{
+    corpus::CorpusId,
+    inputs::{BytesInput, HasTargetBytes},
+    mutators::{MutationResult, Mutator},
+    state::{HasMaxSize, HasRand},
+    Error,
+};
+use libafl_bolts::{
+    nonzero,
+    rands::Rand,
+    tuples::{tuple_list, tuple_list_type},
+    Named,
+};
+
+use crate::{
+    format::spec::{Document, Field, FormatSpec},
+    modules::fault_schedule::FaultSchedule,
+};
+
+const INTERESTING: [u64; 10] = [0, 1, 2, 0x7f, 0x80, 0xff, 0x7fff, 0xffff, 0x7fff_ffff, u64::MAX];
+
+fn below<R: Rand>(rand: &mut R, n: usize) -> usize {
+    rand.below(NonZeroUsize::new(n).unwrap_or(nonzero!(1)))
+}
+
+/// Parse the input, let `mutate` change the document and write it back with fixed up
+/// derived fields. A fault schedule at the end of the input is kept as it is.
+pub(crate) fn mutate_document<S, F>(
+    spec: &FormatSpec,
+    state: &mut S,
+    input: &mut BytesInput,
+    mutate: F,
+) -> Result<MutationResult, Error>
+where
+    S: HasRand + HasMaxSize,
+    F: FnOnce(&mut Document, &mut S) -> Result<MutationResult, Error>,
+{
+    let bytes = input.target_bytes();
+    let (data, schedule) = FaultSchedule::split(&bytes);
+    let has_schedule = data.len() != bytes.len();
+    let Some(mut doc) = spec.parse(data) else {
+        return Ok(MutationResult::Skipped);
+    };
+    drop(bytes);
+    if mutate(&mut doc, state)? == MutationResult::Skipped {
+        return Ok(MutationResult::Skipped);
+    }
+
+    let mut mutated = spec.serialize(&doc);
+    if has_schedule {
+        mutated = schedule.join(&mutated);
+    }
+    if mutated.len() > state.max_size() {
+        return Ok(MutationResult::Skipped);
+    }
+    *input = BytesInput::new(mutated);
+    Ok(MutationResult::Mutated)
+}
+
+/// Indices of the fields mutators may change, derived fields are recomputed anyway
+fn free_fields(spec: &FormatSpec) -> Vec<usize> {
+    (0..spec.fields.len())
+        .filter(|idx| !spec.fields[*idx].is_derived())
+        .collect()
+}
+
+/// A random free field of a random chunk
+fn pick_field<R: Rand>(spec: &FormatSpec, doc: &Document, rand: &mut R) -> Option<(usize, usize)> {
+    let fields = free_fields(spec);
+    if doc.chunks.is_empty() || fields.is_empty() {
+        return None;
+    }
+    let chunk = below(rand, doc.chunks.len());
+    let field = fields[below(rand, fields.len())];
+    Some((chunk, field))
+}
+
+/// Changes a single field: integers get interesting or nearby values, bytes and blobs
+/// get small edits. Blobs may grow or shrink, their length follows.
+#[derive(Debug)]
+pub struct FormatFieldMutator {
+    spec: Rc<FormatSpec>,
+}
+
+impl FormatFieldMutator {
+    pub fn new(spec: Rc<FormatSpec>) -> Self {
+        Self { spec }
+    }
+}
+
+impl<S> Mutator<BytesInput, S> for FormatFieldMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        let spec = self.spec.clone();
+        mutate_document(&spec, state, input, |doc, state| {
+            let rand = state.rand_mut();
+            let Some((chunk, field)) = pick_field(&spec, doc, rand) else {
+                return Ok(MutationResult::Skipped);
+            };
+            let value = &mut doc.chunks[chunk][field];
+            match &spec.fields[field] {
+                Field::Uint(_) => {
+                    let old = spec.endian.read(value);
+                    let new = match rand.below(nonzero!(3)) {
+                        0 => rand.choose(INTERESTING).unwrap(),
+                        1 => old.wrapping_add(rand.below(nonzero!(33)) as u64).wrapping_sub(16),
+                        _ => rand.next(),
+                    };
+                    spec.endian.write(value, new);
+                }
+                Field::Blob { length } => match rand.below(nonzero!(3)) {
+                    // A blob without length runs to the end of the file, it can't grow
+                    0 if length.is_some() => {
+                        let pos = below(rand, value.len() + 1);
+                        let len = below(rand, 16) + 1;
+                        let fill = rand.next() as u8;
+                        value.splice(pos..pos, iter::repeat_n(fill, len));
+                    }
+                    1 if length.is_some() && !value.is_empty() => {
+                        let pos = below(rand, value.len());
+                        let len = below(rand, (value.len() - pos).min(16)) + 1;
+                        value.drain(pos..pos + len);
+                    }
+                    _ if !value.is_empty() => {
+                        let pos = below(rand, value.len());
+                        value[pos] ^= 1 << below(rand, 8);
+                    }
+                    _ => return Ok(MutationResult::Skipped),
+                },
+                _ if !value.is_empty() => {
+                    let pos = below(rand, value.len());
+                    value[pos] = rand.next() as u8;
+                }
+                _ => return Ok(MutationResult::Skipped),
+            }
+            Ok(MutationResult::Mutated)
+        })
+    }
+}
+
+impl Named for FormatFieldMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("FormatFieldMutator");
+        &NAME
+    }
+}
+
+/// Duplicates, removes, swaps or inserts empty chunks
+#[derive(Debug)]
+pub struct FormatChunkMutator {
+    spec: Rc<FormatSpec>,
+}
+
+impl FormatChunkMutator {
+    pub fn new(spec: Rc<FormatSpec>) -> Self {
+        Self { spec }
+    }
+}
+
+impl<S> Mutator<BytesInput, S> for FormatChunkMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        let spec = self.spec.clone();
+        mutate_document(&spec, state, input, |doc, state| {
+            let rand = state.rand_mut();
+            let chunks = &mut doc.chunks;
+            let idx = below(rand, chunks.len());
+            match rand.below(nonzero!(4)) {
+                0 if !chunks.is_empty() => {
+                    let chunk = chunks[idx].clone();
+                    chunks.insert(idx, chunk);
+                }
+                1 if !chunks.is_empty() => {
+                    chunks.remove(idx);
+                }
+                2 if chunks.len() > 1 => {
+                    let other = below(rand, chunks.len());
+                    chunks.swap(idx, other);
+                }
+                3 => chunks.insert(idx.min(chunks.len()), spec.empty_chunk()),
+                _ => return Ok(MutationResult::Skipped),
+            }
+            Ok(MutationResult::Mutated)
+        })
+    }
+}
+
+impl Named for FormatChunkMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("FormatChunkMutator");
+        &NAME
+    }
+}
+
+/// Runs another mutator (e.g. havoc) on the contents of a single blob, so the
+/// structure around it stays intact
+#[derive(Debug)]
+pub struct FormatHavocMutator<M> {
+    spec: Rc<FormatSpec>,
+    inner: M,
+}
+
+impl<M> FormatHavocMutator<M> {
+    pub fn new(spec: Rc<FormatSpec>, inner: M) -> Self {
+        Self { spec, inner }
+    }
+}
+
+impl<M, S> Mutator<BytesInput, S> for FormatHavocMutator<M>
+where
+    M: Mutator<BytesInput, S>,
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        let spec = self.spec.clone();
+        let inner = &mut self.inner;
+        mutate_document(&spec, state, input, |doc, state| {
+            let blobs = spec
+                .fields
+                .iter()
+                .enumerate()
+                .filter(|(_, field)| matches!(field, Field::Blob { length: Some(_) }))
+                .map(|(idx, _)| idx)
+                .collect::<Vec<usize>>();
+            if doc.chunks.is_empty() || blobs.is_empty() {
+                return Ok(MutationResult::Skipped);
+            }
+            let chunk = below(state.rand_mut(), doc.chunks.len());
+            let field = blobs[below(state.rand_mut(), blobs.len())];
+
+            let mut blob = BytesInput::new(doc.chunks[chunk][field].clone());
+            if inner.mutate(state, &mut blob)? == MutationResult::Skipped {
+                return Ok(MutationResult::Skipped);
+            }
+            doc.chunks[chunk][field] = blob.target_bytes().to_vec();
+            Ok(MutationResult::Mutated)
+        })
+    }
+
+    fn post_exec(&mut self, state: &mut S, new_corpus_id: Option<CorpusId>) -> Result<(), Error> {
+        self.inner.post_exec(state, new_corpus_id)
+    }
+}
+
+impl<M> Named for FormatHavocMutator<M> {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("FormatHavocMutator");
+        &NAME
+    }
+}
+
+/// All mutations of a format spec, `inner` is run on single blobs
+pub type FormatMutationsType<M> =
+    tuple_list_type!(FormatFieldMutator, FormatFieldMutator, FormatChunkMutator, FormatHavocMutator<M>);
+
+/// Fields are the most common target, so they are scheduled twice as often
+pub fn format_mutations<M>(spec: &Rc<FormatSpec>, inner: M) -> FormatMutationsType<M> {
+    tuple_list!(
+        FormatFieldMutator::new(spec.clone()),
+        FormatFieldMutator::new(spec.clone()),
+        FormatChunkMutator::new(spec.clone()),
+        FormatHavocMutator::new(spec.clone(), inner)
+    )
+}
@@ -0,0 +1,517 @@
+//! The format description and the documents parsed with it.
+//!
+//! A file is the `magic` followed by chunks until the end of the file; every chunk has the
+//! same list of fields. Length, offset and checksum fields are derived: they are read when
+//! parsing but recomputed whenever a document is written back.
+use std::{collections::HashMap, fs, path::Path};
+
+use libafl::Error;
+use serde::Deserialize;
+
+use crate::format::checksum::Algorithm;
+
+/// A crafted input shouldn't make us allocate forever
+const MAX_CHUNKS: usize = 4096;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn read(self, bytes: &[u8]) -> u64 {
+        let mut buf = [0_u8; 8];
+        match self {
+            Self::Little => {
+                buf[..bytes.len()].copy_from_slice(bytes);
+                u64::from_le_bytes(buf)
+            }
+            Self::Big => {
+                buf[8 - bytes.len()..].copy_from_slice(bytes);
+                u64::from_be_bytes(buf)
+            }
+        }
+    }
+
+    pub fn write(self, bytes: &mut [u8], value: u64) {
+        let len = bytes.len();
+        match self {
+            Self::Little => bytes.copy_from_slice(&value.to_le_bytes()[..len]),
+            Self::Big => bytes.copy_from_slice(&value.to_be_bytes()[8 - len..]),
+        }
+    }
+}
+
+/// A field as written in the spec file, fields refer to each other by name
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum FieldFile {
+    /// Opaque bytes of a fixed size
+    Bytes { name: String, size: usize },
+    /// An integer of `size` bytes
+    Uint { name: String, size: usize },
+    /// The size of the blob `of`, plus `adjust`
+    Length {
+        name: String,
+        size: usize,
+        of: String,
+        #[serde(default)]
+        adjust: i64,
+    },
+    /// The offset of field `of` from the start of the file
+    Offset { name: String, size: usize, of: String },
+    /// Data sized by a length field, or the rest of the file without one
+    Blob { name: String },
+    /// A checksum over the fields `over`, in this order
+    Checksum {
+        name: String,
+        size: usize,
+        algorithm: Algorithm,
+        over: Vec<String>,
+    },
+}
+
+impl FieldFile {
+    fn name(&self) -> &str {
+        match self {
+            Self::Bytes { name, .. }
+            | Self::Uint { name, .. }
+            | Self::Length { name, .. }
+            | Self::Offset { name, .. }
+            | Self::Blob { name }
+            | Self::Checksum { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpecFile {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    endian: Endian,
+    /// Hex encoded bytes the file starts with
+    #[serde(default)]
+    magic: String,
+    chunk: Vec<FieldFile>,
+}
+
+/// A field with its references resolved to field indices
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Bytes(usize),
+    Uint(usize),
+    Length { size: usize, of: usize, adjust: i64 },
+    Offset { size: usize, of: usize },
+    Blob { length: Option<usize> },
+    Checksum { size: usize, algorithm: Algorithm, over: Vec<usize> },
+}
+
+impl Field {
+    /// Fields whose value is recomputed when writing a document
+    pub fn is_derived(&self) -> bool {
+        matches!(self, Self::Length { .. } | Self::Offset { .. } | Self::Checksum { .. })
+    }
+
+    fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Self::Bytes(size)
+            | Self::Uint(size)
+            | Self::Length { size, .. }
+            | Self::Offset { size, .. }
+            | Self::Checksum { size, .. } => Some(*size),
+            Self::Blob { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FormatSpec {
+    pub name: String,
+    pub endian: Endian,
+    pub magic: Vec<u8>,
+    pub fields: Vec<Field>,
+}
+
+/// A parsed file: the raw bytes of each field of each chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub chunks: Vec<Vec<Vec<u8>>>,
+    /// What follows the last chunk that parsed
+    pub trailer: Vec<u8>,
+}
+
+impl FormatSpec {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let source = fs::read_to_string(path)?;
+        Self::from_toml(&source)
+            .map_err(|e| Error::illegal_argument(format!("Invalid format spec {path:?}: {e}")))
+    }
+
+    fn from_toml(source: &str) -> Result<Self, String> {
+        let file: SpecFile = toml::from_str(source).map_err(|e| e.to_string())?;
+        let magic = decode_hex(&file.magic).ok_or("magic is not a hex string")?;
+
+        let names = file
+            .chunk
+            .iter()
+            .enumerate()
+            .map(|(idx, field)| (field.name(), idx))
+            .collect::<HashMap<&str, usize>>();
+        if names.len() != file.chunk.len() {
+            return Err("field names are not unique".to_string());
+        }
+        let lookup = |name: &str| {
+            names
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("unknown field {name}"))
+        };
+
+        let mut fields = Vec::new();
+        for field in &file.chunk {
+            fields.push(match field {
+                FieldFile::Bytes { size, .. } => Field::Bytes(*size),
+                FieldFile::Uint { size, .. } => Field::Uint(*size),
+                FieldFile::Length {
+                    size, of, adjust, ..
+                } => Field::Length {
+                    size: *size,
+                    of: lookup(of)?,
+                    adjust: *adjust,
+                },
+                FieldFile::Offset { size, of, .. } => Field::Offset {
+                    size: *size,
+                    of: lookup(of)?,
+                },
+                FieldFile::Blob { .. } => Field::Blob { length: None },
+                FieldFile::Checksum {
+                    size,
+                    algorithm,
+                    over,
+                    ..
+                } => Field::Checksum {
+                    size: *size,
+                    algorithm: *algorithm,
+                    over: over.iter().map(|name| lookup(name)).collect::<Result<_, _>>()?,
+                },
+            });
+        }
+
+        // Link the blobs to their length fields, which have to come first to parse them
+        for idx in 0..fields.len() {
+            if let Field::Length { of, .. } = fields[idx] {
+                match &mut fields[of] {
+                    Field::Blob { length } if length.is_none() && of > idx => *length = Some(idx),
+                    _ => {
+                        return Err(format!(
+                            "length field {} must come before a blob no other length refers to",
+                            file.chunk[idx].name()
+                        ))
+                    }
+                }
+            }
+        }
+        for (idx, field) in fields.iter().enumerate() {
+            match field {
+                Field::Blob { length: None } if idx + 1 != fields.len() => {
+                    return Err(format!(
+                        "blob {} without length must be the last field",
+                        file.chunk[idx].name()
+                    ));
+                }
+                Field::Uint(size)
+                | Field::Length { size, .. }
+                | Field::Offset { size, .. }
+                | Field::Checksum { size, .. }
+                    if !(1..=8).contains(size) =>
+                {
+                    return Err(format!(
+                        "integer field {} must have 1 to 8 bytes",
+                        file.chunk[idx].name()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        if fields.is_empty() || fields.iter().all(|field| field.fixed_size() == Some(0)) {
+            return Err("a chunk needs at least one non-empty field".to_string());
+        }
+
+        Ok(Self {
+            name: file.name,
+            endian: file.endian,
+            magic,
+            fields,
+        })
+    }
+
+    /// Parse `bytes` trusting the derived fields, as the target would
+    pub fn parse(&self, bytes: &[u8]) -> Option<Document> {
+        let mut pos = self.magic.len();
+        if self.fields.is_empty() || !bytes.starts_with(&self.magic) {
+            return None;
+        }
+
+        let mut chunks = Vec::new();
+        while pos < bytes.len() && chunks.len() < MAX_CHUNKS {
+            let Some((chunk, len)) = self.parse_chunk(&bytes[pos..]) else {
+                break;
+            };
+            chunks.push(chunk);
+            pos += len;
+            if len == 0 {
+                break;
+            }
+        }
+        Some(Document {
+            chunks,
+            trailer: bytes[pos..].to_vec(),
+        })
+    }
+
+    fn parse_chunk(&self, bytes: &[u8]) -> Option<(Vec<Vec<u8>>, usize)> {
+        let mut values: Vec<Vec<u8>> = Vec::with_capacity(self.fields.len());
+        let mut pos = 0;
+        for field in &self.fields {
+            let size = match field {
+                Field::Blob { length: Some(length) } => {
+                    let Field::Length { adjust, .. } = self.fields[*length] else {
+                        unreachable!("blobs are only linked to length fields")
+                    };
+                    let value = self.endian.read(&values[*length]) as i64;
+                    usize::try_from(value.checked_sub(adjust)?).ok()?
+                }
+                Field::Blob { length: None } => bytes.len() - pos,
+                field => field.fixed_size().unwrap(),
+            };
+            let end = pos.checked_add(size)?;
+            values.push(bytes.get(pos..end)?.to_vec());
+            pos = end;
+        }
+        Some((values, pos))
+    }
+
+    /// Write `doc` back, recomputing lengths, offsets and checksums
+    pub fn serialize(&self, doc: &Document) -> Vec<u8> {
+        let mut out = self.magic.clone();
+        for chunk in &doc.chunks {
+            let mut values = chunk.clone();
+            // Positions don't depend on derived values, all derived fields have a fixed size
+            let start = out.len();
+            let positions = values
+                .iter()
+                .scan(start, |pos, value| {
+                    let field_pos = *pos;
+                    *pos += value.len();
+                    Some(field_pos)
+                })
+                .collect::<Vec<usize>>();
+
+            for (idx, field) in self.fields.iter().enumerate() {
+                let value = match field {
+                    Field::Length { of, adjust, .. } => (values[*of].len() as i64 + adjust) as u64,
+                    Field::Offset { of, .. } => positions[*of] as u64,
+                    _ => continue,
+                };
+                self.endian.write(&mut values[idx], value);
+            }
+            // Checksums last, they may cover lengths and offsets
+            for (idx, field) in self.fields.iter().enumerate() {
+                if let Field::Checksum { algorithm, over, .. } = field {
+                    let covered = over
+                        .iter()
+                        .flat_map(|field| values[*field].iter().copied())
+                        .collect::<Vec<u8>>();
+                    let checksum = algorithm.compute(&covered);
+                    self.endian.write(&mut values[idx], checksum);
+                }
+            }
+            for value in values {
+                out.extend_from_slice(&value);
+            }
+        }
+        out.extend_from_slice(&doc.trailer);
+        out
+    }
+
+    /// A new chunk with all fields zeroed and empty blobs
+    pub fn empty_chunk(&self) -> Vec<Vec<u8>> {
+        self.fields
+            .iter()
+            .map(|field| vec![0; field.fixed_size().unwrap_or_default()])
+            .collect()
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.replace([' ', '_'], "");
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &str = r#"
+        name = "png"
+        endian = "big"
+        magic = "89504e470d0a1a0a"
+        chunk = [
+            { kind = "length", name = "length", size = 4, of = "data" },
+            { kind = "bytes", name = "type", size = 4 },
+            { kind = "blob", name = "data" },
+            { kind = "checksum", name = "crc", size = 4, algorithm = "crc32", over = ["type", "data"] },
+        ]
+    "#;
+
+    const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn chunk(typ: &[u8], data: &[u8]) -> Vec<Vec<u8>> {
+        vec![vec![0; 4], typ.to_vec(), data.to_vec(), vec![0; 4]]
+    }
+
+    #[test]
+    fn png_spec() {
+        let spec = FormatSpec::from_toml(PNG).unwrap();
+        assert_eq!(spec.name, "png");
+        assert_eq!(spec.endian, Endian::Big);
+        assert_eq!(spec.magic, PNG_MAGIC);
+        assert_eq!(
+            spec.fields,
+            vec![
+                Field::Length {
+                    size: 4,
+                    of: 2,
+                    adjust: 0
+                },
+                Field::Bytes(4),
+                Field::Blob { length: Some(0) },
+                Field::Checksum {
+                    size: 4,
+                    algorithm: Algorithm::Crc32,
+                    over: vec![1, 2]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn serialize_recomputes_derived_fields() {
+        let spec = FormatSpec::from_toml(PNG).unwrap();
+        let doc = Document {
+            chunks: vec![chunk(b"IEND", b"")],
+            trailer: Vec::new(),
+        };
+        // The IEND chunk every PNG ends with
+        let expected = [PNG_MAGIC, b"\0\0\0\0IEND\xae\x42\x60\x82"].concat();
+        assert_eq!(spec.serialize(&doc), expected);
+    }
+
+    #[test]
+    fn parse_serialize_round_trip() {
+        let spec = FormatSpec::from_toml(PNG).unwrap();
+        let doc = Document {
+            chunks: vec![
+                chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+                chunk(b"tEXt", b"Comment\0fuzzed"),
+                chunk(b"IEND", b""),
+            ],
+            trailer: b"junk".to_vec(),
+        };
+        let bytes = spec.serialize(&doc);
+        let parsed = spec.parse(&bytes).unwrap();
+        assert_eq!(parsed.chunks.len(), 3);
+        assert_eq!(parsed.chunks[1][0], [0, 0, 0, 14]);
+        assert_eq!(parsed.chunks[1][2], b"Comment\0fuzzed");
+        assert_eq!(parsed.trailer, b"junk");
+        assert_eq!(spec.serialize(&parsed), bytes);
+    }
+
+    #[test]
+    fn parse_keeps_what_does_not_fit_as_trailer() {
+        let spec = FormatSpec::from_toml(PNG).unwrap();
+        assert!(spec.parse(b"GIF89a").is_none());
+
+        // The length claims more data than the file holds
+        let bytes = [PNG_MAGIC, b"\0\0\0\x20IDATshort"].concat();
+        let doc = spec.parse(&bytes).unwrap();
+        assert!(doc.chunks.is_empty());
+        assert_eq!(doc.trailer, b"\0\0\0\x20IDATshort");
+        assert_eq!(spec.serialize(&doc), bytes);
+    }
+
+    #[test]
+    fn offsets_and_adjusted_lengths() {
+        let spec = FormatSpec::from_toml(
+            r#"
+            magic = "41 42"
+            chunk = [
+                { kind = "offset", name = "at", size = 2, of = "data" },
+                { kind = "length", name = "len", size = 1, of = "data", adjust = 1 },
+                { kind = "blob", name = "data" },
+            ]
+            "#,
+        )
+        .unwrap();
+        let doc = Document {
+            chunks: vec![
+                vec![vec![0; 2], vec![0], b"abc".to_vec()],
+                vec![vec![0; 2], vec![0], b"d".to_vec()],
+            ],
+            trailer: Vec::new(),
+        };
+        let bytes = spec.serialize(&doc);
+        assert_eq!(bytes, b"AB\x05\x00\x04abc\x0b\x00\x02d");
+        let parsed = spec.parse(&bytes).unwrap();
+        assert_eq!(parsed.chunks[0][2], b"abc");
+        assert_eq!(parsed.chunks[1][2], b"d");
+        assert!(parsed.trailer.is_empty());
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let invalid = [
+            // Blob without length in the middle
+            r#"chunk = [{ kind = "blob", name = "a" }, { kind = "uint", name = "b", size = 1 }]"#,
+            // Length after its blob
+            r#"chunk = [
+                { kind = "blob", name = "a" },
+                { kind = "length", name = "b", size = 1, of = "a" },
+            ]"#,
+            // Duplicate names
+            r#"chunk = [{ kind = "uint", name = "a", size = 1 }, { kind = "uint", name = "a", size = 2 }]"#,
+            // Unknown reference
+            r#"chunk = [{ kind = "offset", name = "a", size = 1, of = "b" }]"#,
+            // Integer too wide
+            r#"chunk = [{ kind = "uint", name = "a", size = 9 }]"#,
+            // Nothing to parse
+            r#"chunk = [{ kind = "bytes", name = "a", size = 0 }]"#,
+            // Not hex
+            r#"magic = "xyz"
+            chunk = [{ kind = "uint", name = "a", size = 1 }]"#,
+        ];
+        for source in invalid {
+            assert!(FormatSpec::from_toml(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(decode_hex("89504e47"), Some(vec![0x89, 0x50, 0x4e, 0x47]));
+        assert_eq!(decode_hex("DE AD_be ef"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
@@ -2,19 +2,26 @@ use std::{
     cell::RefCell,
     fs::{File, OpenOptions},
     io::{self, Write},
+    time::Duration,
 };
 
 use clap::Parser;
 #[cfg(feature = "simplemgr")]
 use libafl::events::SimpleEventManager;
 #[cfg(not(feature = "simplemgr"))]
-use libafl::events::{EventConfig, Launcher, MonitorTypedEventManager};
+use libafl::events::{EventConfig, Launcher, MonitorTypedEventManager, StdLlmpEventHook};
 use libafl::{
     events::{ClientDescription, LlmpEventManager, LlmpRestartingEventManager},
+    inputs::BytesInput,
     monitors::{tui::TuiMonitor, Monitor, MultiMonitor},
     Error,
 };
-use libafl_bolts::{core_affinity::CoreId, current_time, llmp::LlmpBroker, tuples::tuple_list};
+use libafl_bolts::{
+    core_affinity::CoreId,
+    current_time,
+    llmp::LlmpBroker,
+    tuples::tuple_list,
+};
 #[cfg(not(feature = "simplemgr"))]
 use libafl_bolts::{
     shmem::{ShMemProvider, StdShMemProvider},
@@ -26,7 +33,7 @@ use {
     std::os::unix::io::{AsRawFd, FromRawFd},
 };
 
-use crate::{client::Client, options::FuzzerOptions};
+use crate::{client::Client, options::FuzzerOptions, trace};
 use env_logger;
 
 pub struct Fuzzer {
@@ -43,7 +50,12 @@ impl Fuzzer {
     pub fn fuzz(&self) -> Result<(), Error> {
         // log::info!, log::debug! ... will print log into stderr by default
         // println! will print log into stdout
-        env_logger::init();
+        let mut logger = env_logger::Builder::from_default_env();
+        if let Some(filter) = &self.options.log_filter {
+            logger.parse_filters(filter);
+        }
+        logger.init();
+        trace::init_ring(self.options.exec_ring);
 
         log::info!("Starting fuzzer with options: {:?}", self.options);
 
@@ -119,8 +131,8 @@ impl Fuzzer {
         let client = Client::new(&self.options);
 
         #[cfg(not(feature = "simplemgr"))]
-        if self.options.rerun_input.is_some() {
-            // If we want to rerun a single input but we use a restarting mgr, we'll have to create a fake restarting mgr that doesn't actually restart.
+        if self.options.is_single_run() {
+            // If we want to rerun a single input (or minimize the corpus / an input) but we use a restarting mgr, we'll have to create a fake restarting mgr that doesn't actually restart.
             // It's not pretty but better than recompiling with simplemgr.
 
             // Just a random number, let's hope it's free :)
@@ -150,6 +162,11 @@ impl Fuzzer {
             );
         }
 
+        // Before any client writes to its queue again
+        if self.options.resume && !self.options.broker_only && !self.options.is_single_run() {
+            self.options.rotate_queues()?;
+        }
+
         #[cfg(feature = "simplemgr")]
         return client.run(
             None,
@@ -157,11 +174,30 @@ impl Fuzzer {
             ClientDescription::new(0, 0, CoreId(0)),
         );
 
+        // The Launcher refuses to run without client cores, so a broker-only node runs the
+        // broker on its own, connected to the remote broker like the Launcher would
+        #[cfg(not(feature = "simplemgr"))]
+        if self.options.broker_only {
+            log::info!("Running as broker-only node on port {}", self.options.port);
+            let mut broker = LlmpBroker::create_attach_to_tcp(
+                shmem_provider,
+                tuple_list!(StdLlmpEventHook::<BytesInput, M>::new(monitor)?),
+                self.options.port,
+            )?;
+            if let Some(remote_broker_addr) = self.options.remote_broker_addr {
+                log::info!("Connecting to the remote broker at {remote_broker_addr}");
+                broker.inner_mut().connect_b2b(remote_broker_addr)?;
+            }
+            broker.loop_with_timeouts(Duration::from_secs(30), Some(Duration::from_millis(5)));
+            return Ok(());
+        }
+
         // Build and run the Launcher / fuzzer.
         #[cfg(not(feature = "simplemgr"))]
         match Launcher::builder()
             .shmem_provider(shmem_provider)
             .broker_port(self.options.port)
+            .remote_broker_addr(self.options.remote_broker_addr)
             .configuration(EventConfig::from_build_id())
             .monitor(monitor)
             .run_client(|s, m, c| client.run(s, MonitorTypedEventManager::<_, M>::new(m), c))
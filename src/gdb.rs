@@ -0,0 +1,644 @@
+//! A minimal GDB remote protocol stub for replayed inputs.
+//!
+//! The stub takes the place of [`Harness::run`](crate::harness::Harness::run): the modules have
+//! already restored the snapshot and injected the input, so the target is stopped at `start_pc`
+//! when the client attaches. Single-stepping and stepping over breakpoints use temporary
+//! breakpoints on all possible successors of the current instruction.
+//!
+//! A reader thread takes the bytes off the connection, so an interrupt (Ctrl-C in the client)
+//! arrives while the target runs. The next executed block then gets a breakpoint, which stops
+//! the target when it enters the block again: right away in the loop of a hung target.
+use core::time::Duration;
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use libafl::{executors::ExitKind, Error};
+use libafl_bolts::os::unix_signals::Signal;
+use libafl_qemu::{
+    modules::EmulatorModuleTuple, EmulatorModules, GuestAddr, GuestReg, Qemu, QemuExitError,
+    QemuExitReason, QemuShutdownCause, Regs,
+};
+
+/// The executor still arms its timeout while the client debugs the target
+pub const GDB_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The byte a client sends outside of packets to stop the running target
+const INTERRUPT: u8 = 0x03;
+
+/// Set by the reader thread, taken by the next executed block
+static INTERRUPT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// The block that got the breakpoint of an interrupt, 0 if none
+static INTERRUPT_PC: AtomicU64 = AtomicU64::new(0);
+
+/// (QEMU register index, size in bytes) in the order of GDB's `g` packet
+#[cfg(feature = "x86_64")]
+const GDB_REGS: &[(i32, usize)] = &[
+    (0, 8), (1, 8), (2, 8), (3, 8), (4, 8), (5, 8), (6, 8), (7, 8), // rax .. rsp
+    (8, 8), (9, 8), (10, 8), (11, 8), (12, 8), (13, 8), (14, 8), (15, 8), // r8 .. r15
+    (16, 8), // rip
+    (17, 4), // eflags
+];
+#[cfg(feature = "aarch64")]
+const GDB_REGS: &[(i32, usize)] = &[
+    (0, 8), (1, 8), (2, 8), (3, 8), (4, 8), (5, 8), (6, 8), (7, 8),
+    (8, 8), (9, 8), (10, 8), (11, 8), (12, 8), (13, 8), (14, 8), (15, 8),
+    (16, 8), (17, 8), (18, 8), (19, 8), (20, 8), (21, 8), (22, 8), (23, 8),
+    (24, 8), (25, 8), (26, 8), (27, 8), (28, 8), (29, 8), (30, 8), // x0 .. x30
+    (31, 8), // sp
+    (32, 8), // pc
+    (33, 4), // cpsr
+];
+#[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+const GDB_REGS: &[(i32, usize)] = &[];
+
+enum Command {
+    Resume { step: bool },
+    Detach,
+    Kill,
+}
+
+enum Stop {
+    Trap,
+    Interrupted,
+    Exited(ExitKind),
+}
+
+struct Session {
+    stream: TcpStream,
+    /// The bytes of the client, without interrupts, see [`forward`]
+    incoming: Receiver<u8>,
+    breakpoints: BTreeSet<GuestAddr>,
+}
+
+impl Session {
+    /// Read the next packet, `None` once the client went away.
+    /// Acks are skipped, TCP already guarantees the checksum.
+    fn read_packet(&mut self) -> Result<Option<String>, Error> {
+        loop {
+            match self.incoming.recv() {
+                Ok(b'$') => break,
+                Ok(_) => {}
+                Err(_) => return Ok(None),
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match self.incoming.recv() {
+                Ok(b'#') => break,
+                Ok(byte) => data.push(byte),
+                Err(_) => return Ok(None),
+            }
+        }
+        // The checksum
+        if self.incoming.recv().and(self.incoming.recv()).is_err() {
+            return Ok(None);
+        }
+        self.stream.write_all(b"+")?;
+
+        Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), Error> {
+        let checksum = data.bytes().fold(0_u8, u8::wrapping_add);
+        write!(self.stream, "${data}#{checksum:02x}")?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+pub struct GdbStub {
+    session: Mutex<Session>,
+    end_pc: GuestAddr,
+}
+
+impl GdbStub {
+    /// Wait for a GDB client on `port`, executions end at `end_pc` like in the harness
+    pub fn listen(port: u16, end_pc: GuestAddr) -> Result<Self, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        println!("Waiting for gdb on port {port} (target remote :{port}) ...");
+        let (stream, peer) = listener.accept()?;
+        println!("gdb connected from {peer}");
+
+        let (sender, incoming) = mpsc::channel();
+        let reader = stream.try_clone()?;
+        thread::spawn(move || forward(reader, &sender));
+
+        Ok(Self {
+            session: Mutex::new(Session {
+                incoming,
+                stream,
+                breakpoints: BTreeSet::new(),
+            }),
+            end_pc,
+        })
+    }
+
+    fn session(&self) -> Result<MutexGuard<'_, Session>, Error> {
+        self.session
+            .lock()
+            .map_err(|_| Error::illegal_state("gdb session lock poisoned"))
+    }
+
+    fn reply(&self, data: &str) -> Result<(), Error> {
+        self.session()?.send(data)
+    }
+
+    /// Run the current execution under the control of the client
+    pub fn serve(&self, qemu: Qemu) -> Result<ExitKind, Error> {
+        loop {
+            match self.next_command(qemu)? {
+                Command::Resume { step } => match self.resume(qemu, step) {
+                    Ok(Stop::Trap) => self.reply("S05")?,
+                    Ok(Stop::Interrupted) => self.reply("S02")?,
+                    Ok(Stop::Exited(exit_kind)) => {
+                        self.reply("W00")?;
+                        return Ok(exit_kind);
+                    }
+                    Err(e @ Error::Unsupported(..)) => {
+                        log::warn!(target: "gdb", "{e}");
+                        self.reply("E01")?;
+                    }
+                    Err(e) => return Err(e),
+                },
+                Command::Detach => {
+                    let breakpoints = core::mem::take(&mut self.session()?.breakpoints);
+                    for addr in breakpoints.into_iter().filter(|addr| *addr != self.end_pc) {
+                        qemu.remove_breakpoint(addr);
+                    }
+                    return match self.resume(qemu, false)? {
+                        Stop::Trap | Stop::Interrupted => Ok(ExitKind::Ok),
+                        Stop::Exited(exit_kind) => Ok(exit_kind),
+                    };
+                }
+                Command::Kill => process::exit(0),
+            }
+        }
+    }
+
+    /// Called from the QEMU crash hook: report the signal and let the client inspect the
+    /// state of the crash. The guest can't resume from here, the executor's crash handler
+    /// takes over once we return.
+    pub fn on_crash(&self, qemu: Qemu, signal: i32) {
+        let signal = gdb_signal(signal);
+        if self.reply(&format!("S{signal:02x}")).is_err() {
+            return;
+        }
+        match self.next_command(qemu) {
+            Ok(Command::Resume { .. }) => {
+                let _ = self.reply(&format!("X{signal:02x}"));
+            }
+            Ok(Command::Kill) => process::exit(0),
+            Ok(Command::Detach) | Err(_) => {}
+        }
+    }
+
+    /// Answer packets until the client wants the target to run (or leaves)
+    fn next_command(&self, qemu: Qemu) -> Result<Command, Error> {
+        let mut session = self.session()?;
+        loop {
+            let Some(packet) = session.read_packet()? else {
+                return Ok(Command::Detach);
+            };
+            log::debug!(target: "gdb", "<- {packet}");
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => return Ok(Command::Resume { step: false }),
+                Some(b's') => return Ok(Command::Resume { step: true }),
+                Some(b'D') => {
+                    session.send("OK")?;
+                    return Ok(Command::Detach);
+                }
+                Some(b'k') => return Ok(Command::Kill),
+                Some(b'?') => "S05".to_string(),
+                Some(b'g') => read_registers(qemu),
+                Some(b'G') => write_registers(qemu, &packet[1..]),
+                Some(b'p') => read_register(qemu, &packet[1..]),
+                Some(b'P') => write_register(qemu, &packet[1..]),
+                Some(b'm') => read_memory(qemu, &packet[1..]),
+                Some(b'M') => write_memory(qemu, &packet[1..]),
+                Some(b'Z' | b'z') => self.breakpoint(qemu, &mut session, &packet),
+                Some(b'H' | b'T') => "OK".to_string(),
+                _ => query(qemu, &packet),
+            };
+            session.send(&reply)?;
+        }
+    }
+
+    /// `Z0` / `Z1` and `z0` / `z1`, watchpoints are not supported
+    fn breakpoint(&self, qemu: Qemu, session: &mut Session, packet: &str) -> String {
+        let mut fields = packet[1..].split(',');
+        let (Some("0" | "1"), Some(Ok(addr))) = (
+            fields.next(),
+            fields.next().map(|addr| GuestAddr::from_str_radix(addr, 16)),
+        ) else {
+            return String::new();
+        };
+
+        // The harness owns the breakpoint at `end_pc`
+        if packet.starts_with('Z') {
+            if session.breakpoints.insert(addr) && addr != self.end_pc {
+                qemu.set_breakpoint(addr);
+            }
+        } else if session.breakpoints.remove(&addr) && addr != self.end_pc {
+            qemu.remove_breakpoint(addr);
+        }
+        "OK".to_string()
+    }
+
+    fn resume(&self, qemu: Qemu, step: bool) -> Result<Stop, Error> {
+        let breakpoints = self.session()?.breakpoints.clone();
+        let mut pc: GuestAddr = qemu
+            .read_reg(Regs::Pc)
+            .map_err(|e| Error::unknown(format!("Failed to read PC: {e:?}")))?;
+        // Interrupts only stop a running target
+        INTERRUPT_REQUESTED.store(false, Ordering::Relaxed);
+
+        loop {
+            if pc == self.end_pc {
+                return Ok(Stop::Exited(ExitKind::Ok));
+            }
+
+            // Step over the breakpoint at `pc` (or a single instruction)
+            let on_breakpoint = breakpoints.contains(&pc);
+            let temporary = if step || on_breakpoint {
+                let successors = successors(qemu, pc);
+                if successors.is_empty() {
+                    return Err(Error::unsupported(format!(
+                        "Can't determine the successors of the instruction @ {pc:#x}"
+                    )));
+                }
+                successors
+                    .into_iter()
+                    .filter(|addr| !breakpoints.contains(addr) && *addr != self.end_pc)
+                    .collect::<BTreeSet<GuestAddr>>()
+            } else {
+                BTreeSet::new()
+            };
+
+            if on_breakpoint {
+                qemu.remove_breakpoint(pc);
+            }
+            for addr in &temporary {
+                qemu.set_breakpoint(*addr);
+            }
+            let reason = unsafe { qemu.run() };
+            for addr in &temporary {
+                qemu.remove_breakpoint(*addr);
+            }
+            if on_breakpoint {
+                qemu.set_breakpoint(pc);
+            }
+            let interrupt_pc = match INTERRUPT_PC.swap(0, Ordering::Relaxed) {
+                0 => None,
+                addr => Some(addr as GuestAddr),
+            };
+            if let Some(addr) = interrupt_pc {
+                if !breakpoints.contains(&addr) && !temporary.contains(&addr) && addr != self.end_pc
+                {
+                    qemu.remove_breakpoint(addr);
+                }
+            }
+
+            match reason {
+                Ok(QemuExitReason::Breakpoint(addr)) => {
+                    if addr == self.end_pc && !breakpoints.contains(&addr) {
+                        return Ok(Stop::Exited(ExitKind::Ok));
+                    }
+                    if interrupt_pc == Some(addr) {
+                        return Ok(Stop::Interrupted);
+                    }
+                    if step || breakpoints.contains(&addr) {
+                        return Ok(Stop::Trap);
+                    }
+                    // Stepped over the breakpoint, keep going
+                    pc = addr;
+                }
+                Ok(QemuExitReason::End(QemuShutdownCause::HostSignal(Signal::SigInterrupt))) => {
+                    process::exit(0)
+                }
+                Ok(QemuExitReason::End(_) | QemuExitReason::SyncExit) => {
+                    return Ok(Stop::Exited(ExitKind::Ok))
+                }
+                Err(QemuExitError::UnexpectedExit) => return Ok(Stop::Exited(ExitKind::Crash)),
+                Err(e) => return Err(Error::illegal_state(format!("Unexpected QEMU exit: {e:?}"))),
+            }
+        }
+    }
+}
+
+/// Passes the bytes of the client on to the session, until either side goes away. An
+/// interrupt between packets isn't passed on, it asks the target to stop.
+fn forward(stream: TcpStream, sender: &Sender<u8>) {
+    let mut in_packet = false;
+    let mut checksum_left = 0;
+    for byte in BufReader::new(stream).bytes() {
+        let Ok(byte) = byte else {
+            break;
+        };
+        if checksum_left > 0 {
+            checksum_left -= 1;
+            in_packet = checksum_left > 0;
+        } else if in_packet {
+            if byte == b'#' {
+                checksum_left = 2;
+            }
+        } else if byte == b'$' {
+            in_packet = true;
+        } else if byte == INTERRUPT {
+            log::debug!(target: "gdb", "<- interrupt");
+            INTERRUPT_REQUESTED.store(true, Ordering::Relaxed);
+            continue;
+        }
+        if sender.send(byte).is_err() {
+            break;
+        }
+    }
+}
+
+/// Every block gets the exec hook, the target may be interrupted anywhere
+pub fn gen_block<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+) -> Option<u64>
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    Some(pc as u64)
+}
+
+/// Puts the breakpoint of a pending interrupt on the current block
+pub fn on_block<ET, I, S>(
+    qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    id: u64,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    if INTERRUPT_REQUESTED.swap(false, Ordering::Relaxed) {
+        qemu.set_breakpoint(id as GuestAddr);
+        INTERRUPT_PC.store(id, Ordering::Relaxed);
+    }
+}
+
+/// Linux signal numbers to GDB's own numbering, for the signals the target can die from
+fn gdb_signal(signal: i32) -> i32 {
+    match signal {
+        libc::SIGBUS => 10,
+        libc::SIGUSR1 => 30,
+        libc::SIGUSR2 => 31,
+        // SIGILL, SIGTRAP, SIGABRT, SIGFPE, SIGKILL, SIGSEGV, ... share the number
+        signal => signal,
+    }
+}
+
+fn query(qemu: Qemu, packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        "PacketSize=4000".to_string()
+    } else if packet == "qAttached" {
+        "1".to_string()
+    } else if packet == "qC" {
+        "QC1".to_string()
+    } else if packet == "qfThreadInfo" {
+        "m1".to_string()
+    } else if packet == "qsThreadInfo" {
+        "l".to_string()
+    } else if packet == "qOffsets" {
+        let load_addr = qemu.load_addr();
+        format!("Text={load_addr:x};Data={load_addr:x};Bss={load_addr:x}")
+    } else {
+        // Unsupported, including `vCont?` so the client falls back to `c` / `s`
+        String::new()
+    }
+}
+
+fn parse_addr_len(args: &str) -> Option<(GuestAddr, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        GuestAddr::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+fn read_registers(qemu: Qemu) -> String {
+    GDB_REGS
+        .iter()
+        .map(|(idx, size)| {
+            qemu.read_reg(*idx).map_or_else(
+                |_| "xx".repeat(*size),
+                |value: GuestReg| encode_hex(&value.to_le_bytes()[..*size]),
+            )
+        })
+        .collect()
+}
+
+fn write_registers(qemu: Qemu, hex: &str) -> String {
+    let Some(bytes) = decode_hex(hex) else {
+        return "E01".to_string();
+    };
+    let mut offset = 0;
+    for (idx, size) in GDB_REGS {
+        let Some(value) = bytes.get(offset..offset + size) else {
+            break;
+        };
+        let mut raw = [0_u8; size_of::<GuestReg>()];
+        raw[..*size].copy_from_slice(value);
+        if qemu.write_reg(*idx, GuestReg::from_le_bytes(raw)).is_err() {
+            return "E01".to_string();
+        }
+        offset += size;
+    }
+    "OK".to_string()
+}
+
+fn read_register(qemu: Qemu, args: &str) -> String {
+    let reg = usize::from_str_radix(args, 16)
+        .ok()
+        .and_then(|n| GDB_REGS.get(n));
+    match reg {
+        Some((idx, size)) => qemu.read_reg(*idx).map_or_else(
+            |_| "xx".repeat(*size),
+            |value: GuestReg| encode_hex(&value.to_le_bytes()[..*size]),
+        ),
+        None => "E01".to_string(),
+    }
+}
+
+fn write_register(qemu: Qemu, args: &str) -> String {
+    let Some((n, hex)) = args.split_once('=') else {
+        return "E01".to_string();
+    };
+    let reg = usize::from_str_radix(n, 16)
+        .ok()
+        .and_then(|n| GDB_REGS.get(n));
+    let (Some((idx, size)), Some(bytes)) = (reg, decode_hex(hex)) else {
+        return "E01".to_string();
+    };
+    if bytes.len() != *size {
+        return "E01".to_string();
+    }
+    let mut raw = [0_u8; size_of::<GuestReg>()];
+    raw[..*size].copy_from_slice(&bytes);
+    match qemu.write_reg(*idx, GuestReg::from_le_bytes(raw)) {
+        Ok(()) => "OK".to_string(),
+        Err(_) => "E01".to_string(),
+    }
+}
+
+fn read_memory(qemu: Qemu, args: &str) -> String {
+    let Some((addr, len)) = parse_addr_len(args) else {
+        return "E01".to_string();
+    };
+    let mut buf = vec![0_u8; len];
+    match qemu.read_mem(addr, &mut buf) {
+        Ok(()) => encode_hex(&buf),
+        Err(_) => "E14".to_string(),
+    }
+}
+
+fn write_memory(qemu: Qemu, args: &str) -> String {
+    let Some((header, hex)) = args.split_once(':') else {
+        return "E01".to_string();
+    };
+    let (Some((addr, len)), Some(bytes)) = (parse_addr_len(header), decode_hex(hex)) else {
+        return "E01".to_string();
+    };
+    if bytes.len() != len {
+        return "E01".to_string();
+    }
+    match qemu.write_mem(addr, &bytes) {
+        Ok(()) => "OK".to_string(),
+        Err(_) => "E14".to_string(),
+    }
+}
+
+/// Every address the instruction at `pc` may continue at: the next instruction and, for
+/// jumps, calls and returns, the targets we can compute from the current CPU state.
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+fn successors(qemu: Qemu, pc: GuestAddr) -> Vec<GuestAddr> {
+    use capstone::{arch, prelude::*, InsnGroupType};
+
+    use crate::modules::crash_info::{disassembler, named_registers};
+
+    let mut code = [0_u8; 16];
+    if qemu.read_mem(pc, &mut code).is_err() {
+        return Vec::new();
+    }
+    let Some(cs) = disassembler() else {
+        return Vec::new();
+    };
+    let Ok(insns) = cs.disasm_count(&code, pc as u64, 1) else {
+        return Vec::new();
+    };
+    let Some(insn) = insns.first() else {
+        return Vec::new();
+    };
+
+    let mut targets = vec![pc + insn.len() as GuestAddr];
+    let Ok(detail) = cs.insn_detail(insn) else {
+        return targets;
+    };
+    let is_group = |group: InsnGroupType::Type| detail.groups().iter().any(|g| g.0 == group as u8);
+    let is_return = is_group(InsnGroupType::CS_GRP_RET);
+    if !is_return && !is_group(InsnGroupType::CS_GRP_JUMP) && !is_group(InsnGroupType::CS_GRP_CALL) {
+        return targets;
+    }
+
+    let regs = named_registers(qemu);
+    let reg = |id: RegId| -> Option<GuestReg> {
+        if id == RegId::INVALID_REG {
+            Some(0)
+        } else {
+            regs.get(&cs.reg_name(id)?).copied()
+        }
+    };
+    let read_ptr = |addr: GuestAddr| -> Option<GuestAddr> {
+        let mut buf = [0_u8; size_of::<GuestAddr>()];
+        qemu.read_mem(addr, &mut buf).ok()?;
+        Some(GuestAddr::from_le_bytes(buf))
+    };
+
+    let operands = detail.arch_detail().operands();
+    // A register is only a branch target if it is the sole operand (`jmp rax`, `br x1`)
+    let single = operands.len() == 1;
+    for op in &operands {
+        #[cfg(feature = "x86_64")]
+        if let arch::ArchOperand::X86Operand(op) = op {
+            match &op.op_type {
+                arch::x86::X86OperandType::Imm(imm) => targets.push(*imm as GuestAddr),
+                arch::x86::X86OperandType::Reg(id) if single => targets.extend(reg(*id)),
+                arch::x86::X86OperandType::Mem(mem) => {
+                    let base = if mem.base() == RegId(arch::x86::X86Reg::X86_REG_RIP as RegIdInt) {
+                        Some(pc + insn.len() as GuestAddr)
+                    } else {
+                        reg(mem.base())
+                    };
+                    if let (Some(base), Some(index)) = (base, reg(mem.index())) {
+                        let addr = base
+                            .wrapping_add(index.wrapping_mul(mem.scale() as GuestReg))
+                            .wrapping_add(mem.disp() as GuestReg);
+                        targets.extend(read_ptr(addr));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        #[cfg(feature = "aarch64")]
+        if let arch::ArchOperand::Arm64Operand(op) = op {
+            match &op.op_type {
+                arch::arm64::Arm64OperandType::Imm(imm) => targets.push(*imm as GuestAddr),
+                arch::arm64::Arm64OperandType::Reg(id) if single => targets.extend(reg(*id)),
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(feature = "x86_64")]
+    if is_return {
+        targets.extend(regs.get("rsp").and_then(|sp| read_ptr(*sp)));
+    }
+    #[cfg(feature = "aarch64")]
+    if is_return && operands.is_empty() {
+        targets.extend(regs.get("x30").copied());
+    }
+    targets
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+fn successors(_qemu: Qemu, _pc: GuestAddr) -> Vec<GuestAddr> {
+    Vec::new()
+}
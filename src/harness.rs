@@ -1,16 +1,22 @@
+use std::process;
+
 use libafl::{
     executors::ExitKind,
     inputs::{BytesInput, HasTargetBytes},
     Error,
 };
-use libafl_bolts::AsSlice;
+use libafl_bolts::{os::unix_signals::Signal, AsSlice};
 use libafl_qemu::{
-    elf::EasyElf, ArchExtras, CallingConvention, GuestAddr, GuestReg, MmapPerms, Qemu, QemuExitReason, Regs
+    elf::EasyElf, ArchExtras, CallingConvention, GuestAddr, GuestReg, MmapPerms, Qemu,
+    QemuExitError, QemuExitReason, QemuShutdownCause, Regs,
 };
 
+use crate::trace::{self, exec_event};
+
 pub struct Harness {
     qemu: Qemu,
     pub input_addr: GuestAddr,
+    pub end_pc: GuestAddr,
     abort_addr: GuestAddr,
 }
 
@@ -78,7 +84,14 @@ impl Harness {
                         .map_err(|e| Error::unknown(format!("Failed to read PC: {e:?}")))?;
                     println!("PC = {pc:#x}");
                 }
-                _ => panic!("Unexpected QEMU exit."),
+                Ok(QemuExitReason::End(QemuShutdownCause::HostSignal(Signal::SigInterrupt))) => {
+                    process::exit(0)
+                }
+                reason => {
+                    return Err(Error::illegal_state(format!(
+                        "Target did not reach start_pc @ {start_pc:#x}: {reason:?}"
+                    )))
+                }
             }
         }
         qemu.remove_breakpoint(start_pc);
@@ -95,7 +108,12 @@ impl Harness {
         //     log::info!("{:?}", mapping);
         // }
 
-        Ok(Harness { qemu, input_addr, abort_addr: tiff_cleanup_addr })
+        Ok(Harness {
+            qemu,
+            input_addr,
+            end_pc,
+            abort_addr: tiff_cleanup_addr,
+        })
     }
 
     /// If we need to do extra work after forking, we can do that here.
@@ -103,25 +121,47 @@ impl Harness {
     #[expect(clippy::unused_self)]
     pub fn post_fork(&self) {}
 
-    // We didn't do much here, because input has been injected by Custom EmulatorModules
-    pub fn run(&self, _qemu: Qemu) -> ExitKind {
-        println!("Harness Start running");
-
-        unsafe {
-            match _qemu.run() {
-                // It seems that the control will back after the inst at breakpoint addr is executed
-                Ok(QemuExitReason::Breakpoint(_)) => {
-                    println!("QEMU hit breakpoint");
-                    let pc: GuestReg = _qemu
-                        .read_reg(Regs::Pc)
-                        .expect("Failed to read PC");
-                    println!("PC = {pc:#x}");
-                }
-                _ => panic!("Unexpected QEMU exit."),
+    /// Run the target until it reaches `end_pc` and map the reason QEMU stopped to an [`ExitKind`].
+    /// We don't do much else here, because the input has been injected by custom EmulatorModules.
+    ///
+    /// Fatal guest signals never return here, QEMU raises them on the host and the executor's
+    /// crash handler reports `ExitKind::Crash`, see `CrashInfoModule`.
+    /// States the harness can't make sense of are returned as errors.
+    pub fn run(&self, _qemu: Qemu) -> Result<ExitKind, Error> {
+        trace::next_exec();
+        exec_event!("harness", "run");
+        log::trace!(target: "harness", "running target");
+
+        match unsafe { _qemu.run() } {
+            // It seems that the control will back after the inst at breakpoint addr is executed
+            Ok(QemuExitReason::Breakpoint(pc)) if pc == self.end_pc => {
+                exec_event!("harness", "end breakpoint", pc);
+                log::trace!(target: "harness", "end breakpoint @ {pc:#x}");
+                Ok(ExitKind::Ok)
+            }
+            Ok(QemuExitReason::Breakpoint(pc)) => Err(Error::illegal_state(format!(
+                "Unexpected breakpoint @ {pc:#x}"
+            ))),
+            // Ctrl-C
+            Ok(QemuExitReason::End(QemuShutdownCause::HostSignal(Signal::SigInterrupt))) => {
+                process::exit(0)
+            }
+            Ok(QemuExitReason::End(cause)) => {
+                exec_event!("harness", "end");
+                log::debug!(target: "harness", "QEMU ended: {cause:?}");
+                Ok(ExitKind::Ok)
+            }
+            Ok(QemuExitReason::SyncExit) => Err(Error::illegal_state(format!(
+                "Unexpected sync exit before end_pc @ {:#x}",
+                self.end_pc
+            ))),
+            // The guest went away without passing through a breakpoint
+            Err(QemuExitError::UnexpectedExit) => {
+                exec_event!("harness", "unexpected exit");
+                Ok(ExitKind::Crash)
             }
+            Err(e) => Err(Error::illegal_state(format!("Unexpected QEMU exit: {e:?}"))),
         }
-        
-        ExitKind::Ok
     }
 
     // No need to call reset here because the target will crash at first run.
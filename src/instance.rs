@@ -1,16 +1,26 @@
 use core::fmt::Debug;
-use std::{fs, marker::PhantomData, ops::Range, path::PathBuf, process};
+use std::{
+    fs,
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::Arc,
+};
 
 #[cfg(feature = "simplemgr")]
 use libafl::events::SimpleEventManager;
 #[cfg(not(feature = "simplemgr"))]
 use libafl::events::{LlmpRestartingEventManager, MonitorTypedEventManager};
 use libafl::{
-    corpus::{Corpus, InMemoryOnDiskCorpus, OnDiskCorpus}, events::{ClientDescription, EventRestarter, NopEventManager}, executors::{Executor, ShadowExecutor}, feedback_and_fast, feedback_or, feedback_or_fast, feedbacks::{BoolValueFeedback, CrashFeedback, MaxMapFeedback, TimeFeedback, TimeoutFeedback}, fuzzer::{Evaluator, Fuzzer, StdFuzzer}, inputs::BytesInput, monitors::Monitor, mutators::{
-        havoc_mutations, token_mutations::I2SRandReplace, tokens_mutations, StdMOptMutator,
+    corpus::{Corpus, InMemoryOnDiskCorpus, OnDiskCorpus}, events::{ClientDescription, EventRestarter, HasCustomBufHandlers, NopEventManager}, executors::{Executor, ExitKind, ShadowExecutor}, feedback_and_fast, feedback_or, feedback_or_fast, feedbacks::{BoolValueFeedback, CrashFeedback, MaxMapFeedback, TimeFeedback, TimeoutFeedback}, fuzzer::{Evaluator, Fuzzer, StdFuzzer}, inputs::BytesInput, monitors::Monitor, mutators::{
+        havoc_mutations, token_mutations::I2SRandReplace,
         StdScheduledMutator, Tokens,
     }, observers::{CanTrack, HitcountsMapObserver, TimeObserver, VariableMapObserver}, schedulers::{
-        powersched::PowerSchedule, IndexesLenTimeMinimizerScheduler, PowerQueueScheduler,
+        powersched::SchedulerMetadata,
+        IndexesLenTimeMinimizerScheduler, PowerQueueScheduler, ProbabilitySamplingScheduler,
+        RandScheduler, StdWeightedScheduler,
     }, stages::{
         calibrate::CalibrationStage, power::StdPowerMutationalStage, AflStatsStage, IfStage,
         ShadowTracingStage, StagesTuple, StdMutationalStage,
@@ -19,20 +29,46 @@ use libafl::{
 #[cfg(not(feature = "simplemgr"))]
 use libafl_bolts::shmem::StdShMemProvider;
 use libafl_bolts::{
-    core_affinity::CoreId, ownedref::OwnedMutSlice, rands::StdRand, tuples::{tuple_list, Merge, Prepend}
+    core_affinity::CoreId, ownedref::OwnedMutSlice, rands::StdRand, tuples::{tuple_list, Handled, Prepend}
 };
 use libafl_qemu::{
     elf::EasyElf,
     modules::{
         cmplog::CmpLogObserver, edges::EdgeCoverageFullVariant, utils::filters::{NopPageFilter, StdAddressFilter}, AsanModule, EdgeCoverageModule, EmulatorModule, EmulatorModuleTuple, SnapshotModule, StdEdgeCoverageModule
     },
-    Emulator, GuestAddr, Qemu, QemuExecutor,
+    Emulator, GuestAddr, Hook, Qemu, QemuExecutor,
 };
 use libafl_targets::{edges_map_mut_ptr, EDGES_MAP_DEFAULT_SIZE, MAX_EDGES_FOUND};
 use typed_builder::TypedBuilder;
 
 use crate::{
-    feedbacks::ignore_exit::IgnoreExitFeedback, harness::Harness, modules::{InputInjectorModule, RegisterResetModule}, options::FuzzerOptions
+    autodict::AutoDict,
+    cmin::CorpusMinimizer,
+    feedbacks::{
+        cmp_coverage::CmpCoverageFeedback,
+        crash_info::CrashInfoFeedback,
+        hang::{HangFeedback, HangsMeta},
+        ignore_exit::IgnoreExitFeedback,
+        known_crash::{KnownCrashFeedback, KnownCrashesMeta},
+        syscall_coverage::SyscallCoverageFeedback,
+    },
+    format::{format_mutations, ChecksumRules, FixupMutator, Fixups, FormatSpec},
+    gdb::{self, GdbStub, GDB_TIMEOUT},
+    harness::Harness,
+    modules::{
+        checksum_patch::UnpatchedCrashesMeta, sandbox::SandboxPolicy, AllocFailureModule,
+        ChecksumPatchModule, CmpCoverageModule, CrashInfoModule, ExecMeta, HangPcModule,
+        InputInjectorModule, RegisterResetModule, SandboxModule, SyscallTraceModule,
+    },
+    mutators::{tiff_mutations, FaultScheduleMutator, StrategyMutator},
+    options::FuzzerOptions,
+    scheduler::{CoreScheduler, SchedulerKind, ShortInputScore},
+    stages::{
+        cmplog_tokens::receive_cmplog_tokens, share_objectives::receive_objective, AflExportStage,
+        CmpLogTokensStage, ForeignSyncStage, RedQueenStage, ShareObjectivesStage, TrimStage,
+        VerifyCrashesStage, VerifyHangsStage,
+    },
+    tmin::{SignatureSlot, TestcaseMinimizer},
 };
 
 pub type ClientState =
@@ -133,9 +169,35 @@ impl<M: Monitor> Instance<'_, M> {
         // // custom snapshot module and make `SnapshotModule` as its inner field is not supported and will cause a panic
         let snapshot_module = SnapshotModule::new();
         let input_injector_module = InputInjectorModule::new();
+        let crash_info_module = CrashInfoModule::new();
+        let hang_pc_module = HangPcModule::new();
+        let syscall_trace_module = SyscallTraceModule::new(
+            options.syscall_coverage,
+            options.strace.as_deref(),
+        )?;
+        let sandbox_policy = options
+            .sandbox
+            .as_ref()
+            .map(|policy| SandboxPolicy::load(policy.as_deref(), Path::new(&options.output)))
+            .transpose()?;
+        let sandbox_module = SandboxModule::new(sandbox_policy);
+        let alloc_failure_module = AllocFailureModule::new(options.alloc_failure, options.rootfs());
+        // Verifying runs (`-r`, cmin, tmin) see the original checks
+        let checksum_patch_module =
+            ChecksumPatchModule::new(options.checksum_patch && !options.is_single_run());
+        // A value profile is of no use to a single run
+        let cmp_coverage_module =
+            CmpCoverageModule::new(options.cmp_coverage && !options.is_single_run());
 
         // Be careful the order of the modules ...
         let modules = modules
+            .prepend(cmp_coverage_module)
+            .prepend(checksum_patch_module)
+            .prepend(alloc_failure_module)
+            .prepend(sandbox_module)
+            .prepend(syscall_trace_module)
+            .prepend(hang_pc_module)
+            .prepend(crash_info_module)
             .prepend(edge_coverage_module)
             .prepend(input_injector_module)
             .prepend(reg_reset_module)
@@ -185,12 +247,16 @@ impl<M: Monitor> Instance<'_, M> {
             .expect("Could not find back the register reset module")
             .save(qemu);
 
-        // Set the input address for the input injector module
-        emulator
+        // Set the input address for the input injector module,
+        // and let exit() of the target end the execution at `end_pc`
+        let input_injector = emulator
             .modules_mut()
             .get_mut::<InputInjectorModule>()
-            .expect("Could not find back the input injector module")
-            .set_input_addr(harness.input_addr);
+            .expect("Could not find back the input injector module");
+        input_injector.set_input_addr(harness.input_addr);
+        input_injector.set_exit_addr(harness.end_pc);
+        input_injector
+            .set_fault_injection(self.options.fault_injection || self.options.alloc_failure);
 
         /*
          * Add Other Fuzzer Components
@@ -213,12 +279,94 @@ impl<M: Monitor> Instance<'_, M> {
                 .build()?),
         );
 
+        let verify_hangs = VerifyHangsStage::new(self.options.hang_timeout());
+
+        // Crashes that went through patched checksum checks, against the original checks
+        let verify_crashes = IfStage::new(
+            |_, _, _, _| Ok(self.options.checksum_patch),
+            tuple_list!(VerifyCrashesStage),
+        );
+
+        // Import inputs from foreign (AFL++) queues and export ours in AFL++ naming
+        let sync_stage = IfStage::new(
+            |_, _, _, _| Ok(!self.options.foreign_sync.is_empty()),
+            tuple_list!(ForeignSyncStage::new(
+                self.options.foreign_sync.clone(),
+                self.options.sync_interval
+            )),
+        );
+
+        let export_stage = IfStage::new(
+            |_, _, _, _| Ok(self.options.afl_export.is_some()),
+            tuple_list!(AflExportStage::new(
+                self.options
+                    .afl_export_dir(self.client_description.clone())
+                    .unwrap_or_default(),
+                self.options.sync_interval
+            )),
+        );
+
+        // Mutate only the fault schedule of the inputs, to reach error handling paths
+        let fault_stage = IfStage::new(
+            |_, _, _, _| Ok(self.options.fault_injection || self.options.alloc_failure),
+            tuple_list!(StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(
+                FaultScheduleMutator::new(
+                    self.options.fault_injection,
+                    self.options.alloc_failure
+                )
+            )))),
+        );
+
+        // Structure-aware TIFF mutations next to havoc, with consistent offsets
+        let tiff_stage = IfStage::new(
+            |_, _, _, _| Ok(self.options.tiff),
+            tuple_list!(StdMutationalStage::new(StdScheduledMutator::new(
+                tiff_mutations()
+            ))),
+        );
+
+        // Mutations that keep the chunks of `--format` intact
+        let format_spec = self
+            .options
+            .format
+            .as_deref()
+            .map(FormatSpec::load)
+            .transpose()?
+            .map(Rc::new);
+        let with_format = format_spec.is_some();
+        let format_stage = IfStage::new(
+            move |_, _, _, _| Ok(with_format),
+            tuple_list!(StdMutationalStage::new(StdScheduledMutator::new(
+                format_mutations(
+                    &format_spec.clone().unwrap_or_default(),
+                    StdScheduledMutator::new(havoc_mutations())
+                )
+            ))),
+        );
+
+        // Post-processing of the havoc and i2s mutations
+        let fixups = Rc::new(Fixups {
+            spec: format_spec,
+            checksums: self
+                .options
+                .checksums
+                .as_deref()
+                .map(ChecksumRules::load)
+                .transpose()?,
+        });
+
+        let share_stage = IfStage::new(
+            |_, _, _, _| Ok(self.options.share_objectives),
+            tuple_list!(ShareObjectivesStage),
+        );
+
         // Feedback to rate the interestingness of an input
         // This one is composed by two Feedbacks in OR
         let mut feedback = feedback_or!(
             // New maximization map feedback linked to the edges observer and the feedback state
             feedback_and_fast!(
-                map_feedback,
+                // New syscall / argument combinations and closer comparisons count like new edges
+                feedback_or!(map_feedback, SyscallCoverageFeedback, CmpCoverageFeedback),
                 ignore_exit_feedback
             ),
             // Time feedback, this one does not need a feedback state
@@ -226,15 +374,27 @@ impl<M: Monitor> Instance<'_, M> {
         );
 
         // A feedback to choose if an input is a solution or not
-        let mut objective = feedback_and_fast!(
-            CrashFeedback::new(),
-            MaxMapFeedback::new(&edges_observer));
+        let mut objective = feedback_or_fast!(
+            feedback_and_fast!(
+                CrashFeedback::new(),
+                MaxMapFeedback::new(&edges_observer),
+                // Drop crashes we already have, e.g. from the run we resumed
+                KnownCrashFeedback,
+                // Attach the guest signal to the solution
+                CrashInfoFeedback
+            ),
+            // Verified timeouts go to `hangs/`, never to the solutions
+            feedback_and_fast!(
+                TimeoutFeedback::new(),
+                HangFeedback::new(self.options.hangs_dir(self.client_description.clone()))
+            )
+        );
 
         // // If not restarting, create a State from scratch
         let mut state = match state {
             Some(x) => x,
             None => {
-                StdState::new(
+                let mut state = StdState::new(
                     // RNG
                     StdRand::new(),
                     // Corpus that will be evolved, we keep it in memory for performance
@@ -249,16 +409,51 @@ impl<M: Monitor> Instance<'_, M> {
                     &mut feedback,
                     // Same for objective feedbacks
                     &mut objective,
-                )?
+                )?;
+
+                if self.options.resume {
+                    let known_crashes = KnownCrashesMeta::load(&self.options.crashes_dirs())?;
+                    log::info!(
+                        "Resuming with {} known crashes, {} crash signatures",
+                        known_crashes.len(),
+                        known_crashes.signatures()
+                    );
+                    state.add_metadata(known_crashes);
+                }
+                state
             }
         };
 
-        // A minimization+queue policy to get testcasess from the corpus
-        let scheduler = IndexesLenTimeMinimizerScheduler::new(
-            &edges_observer,
-            PowerQueueScheduler::new(&mut state, &edges_observer, PowerSchedule::fast()),
+        // A verification that crashed the previous process is over, its crash was stored
+        if let Ok(unpatched) = state.metadata_mut::<UnpatchedCrashesMeta>() {
+            unpatched.set_verifying(None);
+        }
+        if let Ok(hangs) = state.metadata_mut::<HangsMeta>() {
+            hangs.set_verifying(false);
+        }
+
+        // A minimization policy over the scheduler and power schedule of this core
+        let kind = self.options.scheduler_of(core_id);
+        let schedule = self.options.power_schedule_of(core_id).schedule();
+        log::info!("Scheduler: {kind:?}, power schedule: {schedule:?}");
+        let base = CoreScheduler::new(
+            (kind == SchedulerKind::Queue)
+                .then(|| PowerQueueScheduler::new(&mut state, &edges_observer, schedule)),
+            (kind == SchedulerKind::Weighted).then(|| {
+                StdWeightedScheduler::with_schedule(&mut state, &edges_observer, Some(schedule))
+            }),
+            (kind == SchedulerKind::Probability)
+                .then(ProbabilitySamplingScheduler::<ShortInputScore>::new),
+            (kind == SchedulerKind::Rand).then(RandScheduler::new),
         );
+        // Calibration and the power stage run on every core, they need the power schedule
+        if !state.has_metadata::<SchedulerMetadata>() {
+            state.add_metadata(SchedulerMetadata::new(Some(schedule)));
+        }
+        let scheduler = IndexesLenTimeMinimizerScheduler::new(&edges_observer, base);
 
+        let edges_handle = edges_observer.handle();
+        let time_handle = time_observer.handle();
         let observers = tuple_list!(edges_observer, time_observer);
 
         let mut tokens = Tokens::new();
@@ -273,14 +468,62 @@ impl<M: Monitor> Instance<'_, M> {
             tokens.add_from_file(tokenfile)?;
         }
 
+        // Libraries are mapped by now, the harness ran to `start_pc`
+        if self.options.auto_dict && !self.options.is_single_run() {
+            let dict = AutoDict::extract(qemu, self.options.rootfs().as_deref());
+            for token in dict.tokens() {
+                let _ = tokens.add_token(token);
+            }
+            if let Some(path) = &self.options.dump_dict {
+                // Every client extracts the same tokens
+                if self.client_description.id() == 0 {
+                    dict.dump(path)?;
+                }
+            }
+        }
+
         state.add_metadata(tokens);
 
+        // Tokens the cmplog cores promote are broadcast to every client
+        if self.options.cmplog_tokens {
+            self.mgr.add_custom_buf_handler(Box::new(|state, tag, buf| {
+                receive_cmplog_tokens(state, tag, buf)
+            }));
+        }
+
+        // Solutions of other clients are re-executed by the `ShareObjectivesStage`
+        if self.options.share_objectives {
+            self.mgr.add_custom_buf_handler(Box::new(|state, tag, buf| {
+                receive_objective(state, tag, buf)
+            }));
+        }
+
         harness.post_fork();
+
+        // Replay under the control of a GDB client instead of just running the target
+        let gdb = match self.options.gdb {
+            Some(port) => Some(Arc::new(GdbStub::listen(port, harness.end_pc)?)),
+            None => None,
+        };
         
         // For current testing, the harness only needs to run once, so we do not need to reset the program state.
+        let gdb_stub = gdb.clone();
         let mut harness = |_emulator: &mut Emulator<_, _, _, _, _, _, _>,
-                           _state: &mut _,
-                           input: &BytesInput| harness.run(_emulator.qemu());
+                           _state: &mut ClientState,
+                           input: &BytesInput| {
+            let result = match &gdb_stub {
+                Some(gdb) => gdb.serve(_emulator.qemu()),
+                None => harness.run(_emulator.qemu()),
+            };
+            result.unwrap_or_else(|e| {
+                // Neither a crash nor a new corpus entry, see `IgnoreExitFeedback`
+                log::error!("Discarding execution: {e}");
+                if let Ok(exec_meta) = _state.metadata_mut::<ExecMeta>() {
+                    exec_meta.ignore = true;
+                }
+                ExitKind::Ok
+            })
+        };
 
         // A fuzzer with feedbacks and a corpus scheduler
         let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
@@ -291,6 +534,24 @@ impl<M: Monitor> Instance<'_, M> {
                 .unwrap_or_else(|_| panic!("Could not load file {rerun_input:?}"));
             let input = BytesInput::new(bytes);
 
+            // Let the client inspect a guest crash before the crash handler takes over, and
+            // interrupt the running target
+            let timeout = if let Some(gdb) = gdb {
+                emulator
+                    .modules_mut()
+                    .crash_closure(Box::new(move |qemu, _emulator_modules, signal| {
+                        gdb.on_crash(qemu, signal);
+                    }));
+                emulator.modules_mut().blocks(
+                    Hook::Function(gdb::gen_block),
+                    Hook::Empty,
+                    Hook::Function(gdb::on_block),
+                );
+                GDB_TIMEOUT
+            } else {
+                self.options.timeout
+            };
+
             let mut executor = QemuExecutor::new(
                 emulator,
                 &mut harness,
@@ -298,7 +559,7 @@ impl<M: Monitor> Instance<'_, M> {
                 &mut fuzzer,
                 &mut state,
                 &mut self.mgr,
-                self.options.timeout,
+                timeout,
             )?;
 
             executor
@@ -313,12 +574,8 @@ impl<M: Monitor> Instance<'_, M> {
             process::exit(0);
         }
 
-        if self
-            .options
-            .is_cmplog_core(self.client_description.core_id())
-        {
-            // Create a QEMU in-process executor
-            let executor = QemuExecutor::new(
+        if let Some(cmin_dir) = &self.options.cmin {
+            let mut executor = QemuExecutor::new(
                 emulator,
                 &mut harness,
                 observers,
@@ -328,35 +585,31 @@ impl<M: Monitor> Instance<'_, M> {
                 self.options.timeout,
             )?;
 
-            // Create an observation channel using cmplog map
-            let cmplog_observer = CmpLogObserver::new("cmplog", true);
-
-            let mut executor = ShadowExecutor::new(executor, tuple_list!(cmplog_observer));
-
-            let tracing = ShadowTracingStage::new(&mut executor);
-
-            // Setup a randomic Input2State stage
-            let i2s = StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(
-                I2SRandReplace::new()
-            )));
+            let mut queue_dirs = self.options.queue_dirs();
+            if queue_dirs.is_empty() {
+                log::warn!("No queue directories found, minimizing the input directory instead");
+                queue_dirs.push(self.options.input_dir());
+            }
 
-            // Setup a MOPT mutator
-            let mutator = StdMOptMutator::new(
+            CorpusMinimizer::new(edges_handle, time_handle).minimize(
+                &mut fuzzer,
+                &mut executor,
                 &mut state,
-                havoc_mutations().merge(tokens_mutations()),
-                7,
-                5,
+                &mut self.mgr,
+                &queue_dirs,
+                cmin_dir,
             )?;
+            process::exit(0);
+        }
 
-            let power: StdPowerMutationalStage<_, _, BytesInput, _, _, _> =
-                StdPowerMutationalStage::new(mutator);
-
-            // The order of the stages matter!
-            let mut stages = tuple_list!(calibration, tracing, i2s, power, stats_stage);
+        if let Some(tmin_input) = &self.options.tmin {
+            let slot = SignatureSlot::new()?;
+            emulator
+                .modules_mut()
+                .crash_closure(Box::new(move |qemu, _emulator_modules, signal| {
+                    slot.on_crash(qemu, signal)
+                }));
 
-            self.fuzz(&mut state, &mut fuzzer, &mut executor, &mut stages)
-        } else {
-            // Create a QEMU in-process executor
             let mut executor = QemuExecutor::new(
                 emulator,
                 &mut harness,
@@ -367,12 +620,94 @@ impl<M: Monitor> Instance<'_, M> {
                 self.options.timeout,
             )?;
 
-            // Setup an havoc mutator with a mutational stage
-            let mutator = StdScheduledMutator::new(havoc_mutations().merge(tokens_mutations()));
-            let mut stages = tuple_list!(StdMutationalStage::new(mutator));
+            let bytes = fs::read(tmin_input)
+                .unwrap_or_else(|_| panic!("Could not load file {tmin_input:?}"));
+            let minimized = TestcaseMinimizer::new(self.options.tmin_mode, edges_handle, slot)
+                .minimize(&mut fuzzer, &mut executor, &mut state, &mut self.mgr, bytes)?;
 
-            self.fuzz(&mut state, &mut fuzzer, &mut executor, &mut stages)
+            let tmin_output = self.options.tmin_output().unwrap();
+            fs::write(&tmin_output, &minimized)?;
+            println!("Minimized input ({} bytes) written to {tmin_output:?}", minimized.len());
+            process::exit(0);
         }
+
+        // Create a QEMU in-process executor
+        let executor = QemuExecutor::new(
+            emulator,
+            &mut harness,
+            observers,
+            &mut fuzzer,
+            &mut state,
+            &mut self.mgr,
+            self.options.timeout,
+        )?;
+
+        // Create an observation channel using cmplog map, the shadow executor only runs it
+        // in the tracing stage
+        let cmplog_observer = CmpLogObserver::new("cmplog", true);
+
+        let mut executor = ShadowExecutor::new(executor, tuple_list!(cmplog_observer));
+
+        let tracing = ShadowTracingStage::new(&mut executor);
+
+        // Promote the operands the tracing keeps seeing to tokens
+        let cmplog_tokens = IfStage::new(
+            |_, _, _, _| Ok(self.options.cmplog_tokens),
+            tuple_list!(CmpLogTokensStage),
+        );
+
+        // Colorization and input-to-state replacement, once per corpus entry
+        let redqueen = IfStage::new(
+            |_, _, _, _| Ok(self.options.redqueen),
+            tuple_list!(RedQueenStage::new()),
+        );
+
+        // Setup a randomic Input2State stage
+        let i2s = StdMutationalStage::new(FixupMutator::new(
+            fixups.clone(),
+            StdScheduledMutator::new(tuple_list!(I2SRandReplace::new())),
+        ));
+
+        // Only the cmplog cores log comparisons, see `Client::run`
+        let cmplog = self.options.is_cmplog_core(core_id);
+        let cmplog_stages = IfStage::new(
+            move |_, _, _, _| Ok(cmplog),
+            tuple_list!(tracing, cmplog_tokens, redqueen, i2s),
+        );
+
+        let trim_stage = IfStage::new(|_, _, _, _| Ok(self.options.trim), tuple_list!(TrimStage));
+
+        // Havoc by default, MOpt on the cmplog cores
+        let strategy = StrategyMutator::new(
+            &mut state,
+            self.options.mutator_of(core_id),
+            self.options.mopt_params(),
+            fixups.spec.as_ref(),
+        )?;
+        let mutator = FixupMutator::new(fixups, strategy);
+
+        let power: StdPowerMutationalStage<_, _, BytesInput, _, _, _> =
+            StdPowerMutationalStage::new(mutator);
+
+        // The same pipeline on every core, the options pick the stages that run.
+        // The order of the stages matter!
+        let mut stages = tuple_list!(
+            verify_crashes,
+            verify_hangs,
+            calibration,
+            trim_stage,
+            cmplog_stages,
+            power,
+            tiff_stage,
+            format_stage,
+            fault_stage,
+            sync_stage,
+            export_stage,
+            share_stage,
+            stats_stage
+        );
+
+        self.fuzz(&mut state, &mut fuzzer, &mut executor, &mut stages)
     }
 
     fn fuzz<Z, E, ST>(
@@ -397,6 +732,17 @@ impl<M: Monitor> Instance<'_, M> {
                     process::exit(0);
                 });
             println!("We imported {} inputs from disk.", state.corpus().count());
+
+            if self.options.resume {
+                let queue_dirs = self.options.resumed_queue_dirs();
+                let files = FuzzerOptions::corpus_files(&queue_dirs)?;
+                state.load_initial_inputs_by_filenames(fuzzer, executor, &mut self.mgr, &files)?;
+                println!(
+                    "We resumed {} queue entries from {queue_dirs:?}, corpus size is now {}.",
+                    files.len(),
+                    state.corpus().count()
+                );
+            }
         }
 
         if let Some(iters) = self.options.iterations {
@@ -1,16 +1,34 @@
 //! A libfuzzer-like fuzzer using qemu for binary-only coverage
 #[cfg(target_os = "linux")]
+mod autodict;
+#[cfg(target_os = "linux")]
 mod client;
 #[cfg(target_os = "linux")]
+mod cmin;
+#[cfg(target_os = "linux")]
+mod format;
+#[cfg(target_os = "linux")]
 mod fuzzer;
 #[cfg(target_os = "linux")]
+mod gdb;
+#[cfg(target_os = "linux")]
 mod harness;
 #[cfg(target_os = "linux")]
 mod instance;
 mod modules;
 #[cfg(target_os = "linux")]
+mod mutators;
+#[cfg(target_os = "linux")]
 mod options;
 #[cfg(target_os = "linux")]
+mod scheduler;
+#[cfg(target_os = "linux")]
+mod stages;
+#[cfg(target_os = "linux")]
+mod tmin;
+#[cfg(target_os = "linux")]
+mod trace;
+#[cfg(target_os = "linux")]
 mod version;
 mod feedbacks;
 
@@ -0,0 +1,304 @@
+//! Allocation failures of the target, driven by the fault schedule of the input.
+//!
+//! `malloc`, `calloc` and `realloc` of the guest libc are hooked at their entry. When the
+//! schedule says the current allocation should fail, the call runs normally but its result
+//! is replaced by `NULL` once it returns to the caller, so the allocator state stays
+//! consistent (the allocation is leaked until the snapshot is restored).
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use libafl::{executors::ExitKind, observers::ObserversTuple, Error, HasMetadata};
+use libafl_qemu::{
+    elf::EasyElf,
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    EmulatorModules, GuestAddr, GuestReg, Hook, Qemu, Regs,
+};
+
+use crate::{
+    modules::{fault_schedule::FaultCountsMeta, InputInjectorModule},
+    trace::exec_event,
+};
+
+const ALLOCATORS: [&str; 3] = ["malloc", "calloc", "realloc"];
+
+#[derive(Debug, Default)]
+pub struct AllocFailureModule {
+    enabled: bool,
+    /// The `-L` directory of QEMU, where the guest libc lives
+    rootfs: Option<PathBuf>,
+    /// Allocations of the current execution
+    count: usize,
+    /// The return sites of the allocations that have to fail, with the stack pointer after
+    /// the return, innermost last. The same site is reached again by recursive calls.
+    pending: Vec<(GuestAddr, GuestAddr)>,
+    return_sites: HashSet<GuestAddr>,
+    failed: Vec<usize>,
+}
+
+impl AllocFailureModule {
+    /// A disabled module doesn't install any hook
+    pub fn new(enabled: bool, rootfs: Option<PathBuf>) -> Self {
+        Self {
+            enabled,
+            rootfs,
+            ..Self::default()
+        }
+    }
+
+    /// Indices of the allocations that failed in the current execution
+    pub fn failed(&self) -> &[usize] {
+        &self.failed
+    }
+
+    /// The guest libc, as mapped by QEMU, and its load address
+    fn find_libc(&self, qemu: Qemu) -> Option<(PathBuf, GuestAddr)> {
+        let mut libc: Option<(String, GuestAddr)> = None;
+        for map in qemu.mappings() {
+            let Some(path) = map.path() else {
+                continue;
+            };
+            let name = Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if !(name.starts_with("libc.so") || name.starts_with("libc-")) {
+                continue;
+            }
+            match &mut libc {
+                Some((_, base)) => *base = (*base).min(map.start()),
+                None => libc = Some((path.clone(), map.start())),
+            }
+        }
+        let (path, base) = libc?;
+        Some((host_path(&path, self.rootfs.as_deref())?, base))
+    }
+
+    fn resolve_allocators(&self, qemu: Qemu) -> Result<Vec<GuestAddr>, Error> {
+        let (path, base) = self
+            .find_libc(qemu)
+            .ok_or_else(|| Error::key_not_found("libc is not mapped or not found in the rootfs"))?;
+        log::info!("Hooking allocators of {path:?} @ {base:#x}");
+
+        let mut elf_buffer = Vec::new();
+        let elf = EasyElf::from_file(&path, &mut elf_buffer)?;
+        let goblin = elf.goblin();
+
+        let mut addrs = Vec::new();
+        for name in ALLOCATORS {
+            // A stripped libc only has dynamic symbols
+            let addr = elf.resolve_symbol(name, base).or_else(|| {
+                goblin
+                    .dynsyms
+                    .iter()
+                    .find(|sym| sym.st_value != 0 && goblin.dynstrtab.get_at(sym.st_name) == Some(name))
+                    .map(|sym| base + sym.st_value as GuestAddr)
+            });
+            match addr {
+                Some(addr) => addrs.push(addr),
+                None => log::warn!("Symbol {name} not found in {path:?}"),
+            }
+        }
+        Ok(addrs)
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for AllocFailureModule
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        log::debug!("AllocFailureModule::first_exec running ...");
+
+        // libc is only mapped once the target ran to `start_pc`
+        match self.resolve_allocators(_qemu) {
+            Ok(addrs) => {
+                for addr in addrs {
+                    _emulator_modules.instructions(addr, Hook::Function(on_alloc::<ET, I, S>), true);
+                }
+            }
+            Err(e) => log::error!("Allocation failures disabled: {e}"),
+        }
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        self.count = 0;
+        self.pending.clear();
+        self.failed.clear();
+    }
+
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if self.enabled {
+            let counts = _state.metadata_or_insert_with(FaultCountsMeta::default);
+            counts.allocs = counts.allocs.max(self.count);
+        }
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+/// A file QEMU mapped for the guest on the host: QEMU may report the guest path, which
+/// only exists below the rootfs. With a rootfs, the host file of the same path is the one of
+/// another architecture or libc version, so it is never used.
+pub fn host_path(path: &str, rootfs: Option<&Path>) -> Option<PathBuf> {
+    let path = PathBuf::from(path);
+    let path = match rootfs {
+        Some(rootfs) if !path.starts_with(rootfs) => {
+            rootfs.join(path.strip_prefix("/").unwrap_or(&path))
+        }
+        _ => path,
+    };
+    path.exists().then_some(path)
+}
+
+/// Where the allocator returns to and the stack pointer once it returned, read at its entry
+fn return_site(qemu: Qemu) -> Option<(GuestAddr, GuestAddr)> {
+    #[cfg(feature = "x86_64")]
+    {
+        let sp: GuestReg = qemu.read_reg(Regs::Rsp).ok()?;
+        let mut buf = [0_u8; size_of::<GuestAddr>()];
+        qemu.read_mem(sp as GuestAddr, &mut buf).ok()?;
+        // `ret` pops the return address
+        let ret_sp = sp as GuestAddr + size_of::<GuestAddr>() as GuestAddr;
+        Some((GuestAddr::from_le_bytes(buf), ret_sp))
+    }
+    #[cfg(feature = "aarch64")]
+    {
+        let lr: GuestReg = qemu.read_reg(Regs::Lr).ok()?;
+        let sp: GuestReg = qemu.read_reg(Regs::Sp).ok()?;
+        Some((lr as GuestAddr, sp as GuestAddr))
+    }
+    #[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+    {
+        let _ = qemu;
+        None
+    }
+}
+
+fn stack_pointer(qemu: Qemu) -> Option<GuestAddr> {
+    #[cfg(feature = "x86_64")]
+    let sp = Regs::Rsp;
+    #[cfg(feature = "aarch64")]
+    let sp = Regs::Sp;
+    #[cfg(any(feature = "x86_64", feature = "aarch64"))]
+    {
+        qemu.read_reg(sp).ok().map(|sp: GuestReg| sp as GuestAddr)
+    }
+    #[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+    {
+        let _ = qemu;
+        None
+    }
+}
+
+fn on_alloc<ET, I, S>(
+    qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    _pc: GuestAddr,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let module = emulator_modules
+        .get_mut::<AllocFailureModule>()
+        .expect("Failed to get AllocFailureModule");
+    let index = module.count;
+    module.count += 1;
+
+    let fail = emulator_modules
+        .get_mut::<InputInjectorModule>()
+        .is_some_and(|injector| injector.faults().alloc_fault(index));
+    if !fail {
+        return;
+    }
+    let Some((ret_addr, sp)) = return_site(qemu) else {
+        return;
+    };
+
+    exec_event!("alloc", "fail", index);
+    log::trace!(target: "injector", "allocation {index} returns NULL at {ret_addr:#x}");
+    let module = emulator_modules
+        .get_mut::<AllocFailureModule>()
+        .expect("Failed to get AllocFailureModule");
+    module.pending.push((ret_addr, sp));
+    module.failed.push(index);
+    if module.return_sites.insert(ret_addr) {
+        emulator_modules.instructions(ret_addr, Hook::Function(on_alloc_return::<ET, I, S>), true);
+    }
+}
+
+fn on_alloc_return<ET, I, S>(
+    qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let module = emulator_modules
+        .get_mut::<AllocFailureModule>()
+        .expect("Failed to get AllocFailureModule");
+    // The same return site may be reached by a call that doesn't fail
+    let Some(sp) = stack_pointer(qemu) else {
+        return;
+    };
+    let Some(idx) = module.pending.iter().rposition(|pending| *pending == (pc, sp)) else {
+        return;
+    };
+    // Failing allocations above it never returned, e.g. the target left them with `longjmp`
+    module.pending.truncate(idx);
+
+    #[cfg(feature = "x86_64")]
+    let ret = Regs::Rax;
+    #[cfg(feature = "aarch64")]
+    let ret = Regs::X0;
+    #[cfg(any(feature = "x86_64", feature = "aarch64"))]
+    if qemu.write_reg(ret, 0 as GuestReg).is_err() {
+        log::error!("Failed to fail the allocation returning to {pc:#x}");
+    }
+    #[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+    let _ = qemu;
+}
@@ -0,0 +1,473 @@
+//! Patches out checksum checks of the target while fuzzing.
+//!
+//! A 4-byte comparison is taken for a checksum check when one operand is stored in the input
+//! (in either byte order), the other one isn't, and the other one changes from input to input,
+//! as a value computed from the input does. Once a comparison was seen like that in
+//! [`MIN_EXECS`] executions, the conditional branch after it is rewritten to always take the
+//! "equal" path.
+//!
+//! Patches are only applied while fuzzing, `-r`, cmin and tmin run the original code. For every
+//! patched check an execution hits, the stored and the computed value are recorded, so the
+//! checksums of a crashing input can be fixed. The fixed input is then queued in
+//! [`UnpatchedCrashesMeta`] instead of being stored, and the `VerifyCrashesStage` re-executes
+//! it with the original checks: only if it crashes again, it ends up in the solutions.
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use libafl::{executors::ExitKind, inputs::HasTargetBytes, observers::ObserversTuple, HasMetadata};
+use libafl_bolts::AsSlice;
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    EmulatorModules, GuestAddr, Hook, Qemu,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{modules::fault_schedule::FaultSchedule, trace::exec_event};
+
+/// Executions a comparison has to look like a checksum check in before it is patched
+pub const MIN_EXECS: usize = 3;
+
+/// Checked patches per execution, for the crash metadata. A check may be hit many times, e.g.
+/// once per chunk of a PNG.
+const MAX_HITS: usize = 256;
+
+/// Crashes with fixed checksums are kept at most this many at a time until they are verified
+const MAX_PENDING_CRASHES: usize = 16;
+
+/// Comparisons with values of few set bits are mostly against small constants
+const MIN_BITS: u32 = 4;
+
+/// A patched check hit in the current execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChecksumHit {
+    pub pc: GuestAddr,
+    /// The value in the input
+    pub stored: u32,
+    /// The value the target computed
+    pub computed: u32,
+    /// Whether `stored` is big endian in the input
+    pub big_endian: bool,
+}
+
+impl ChecksumHit {
+    /// Replace the stored checksum in `bytes` by the computed one
+    pub fn fix(&self, bytes: &mut [u8]) {
+        let (stored, computed) = if self.big_endian {
+            (self.stored.to_be_bytes(), self.computed.to_be_bytes())
+        } else {
+            (self.stored.to_le_bytes(), self.computed.to_le_bytes())
+        };
+        for idx in 0..bytes.len().saturating_sub(3) {
+            if bytes[idx..idx + 4] == stored {
+                bytes[idx..idx + 4].copy_from_slice(&computed);
+            }
+        }
+    }
+}
+
+/// Crashes that went through patched checks, waiting to be re-executed without the patches
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UnpatchedCrashesMeta {
+    /// The inputs with fixed checksums, and the checks they went through
+    pending: VecDeque<(Vec<u8>, Vec<ChecksumHit>)>,
+    /// The checks of the input the `VerifyCrashesStage` re-executes right now
+    verifying: Option<Vec<ChecksumHit>>,
+}
+
+impl UnpatchedCrashesMeta {
+    /// Queue a crash, unless too many are waiting already
+    pub fn push_pending(&mut self, bytes: Vec<u8>, hits: Vec<ChecksumHit>) -> bool {
+        if self.pending.len() >= MAX_PENDING_CRASHES
+            || self.pending.iter().any(|(pending, _)| *pending == bytes)
+        {
+            return false;
+        }
+        self.pending.push_back((bytes, hits));
+        true
+    }
+
+    pub fn pop_pending(&mut self) -> Option<(Vec<u8>, Vec<ChecksumHit>)> {
+        self.pending.pop_front()
+    }
+
+    pub fn is_verifying(&self) -> bool {
+        self.verifying.is_some()
+    }
+
+    pub fn set_verifying(&mut self, hits: Option<Vec<ChecksumHit>>) {
+        self.verifying = hits;
+    }
+
+    /// The checks of the verified crash, ending the verification
+    pub fn take_verifying(&mut self) -> Option<Vec<ChecksumHit>> {
+        self.verifying.take()
+    }
+}
+
+libafl_bolts::impl_serdeany!(UnpatchedCrashesMeta);
+
+#[derive(Debug, Default)]
+struct Site {
+    execs: usize,
+    last_exec: u64,
+    computed: HashSet<u32>,
+    /// Set once the site was patched or found unpatchable
+    done: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ChecksumPatchModule {
+    enabled: bool,
+    exec: u64,
+    input: Vec<u8>,
+    /// All 4-byte little endian windows of the input, built on the first comparison
+    windows: Option<HashSet<u32>>,
+    sites: HashMap<GuestAddr, Site>,
+    /// Sites to patch after the execution
+    pending: Vec<GuestAddr>,
+    patched: HashSet<GuestAddr>,
+    /// Branches already rewritten, checks may share one, with their original and patched bytes
+    branches: HashMap<GuestAddr, (Vec<u8>, Vec<u8>)>,
+    /// Set while a crash is verified against the original checks
+    suspended: bool,
+    hits: Vec<ChecksumHit>,
+}
+
+impl ChecksumPatchModule {
+    /// A disabled module doesn't install any hook
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    /// The patched checks hit in the current execution
+    pub fn hits(&self) -> &[ChecksumHit] {
+        &self.hits
+    }
+
+    /// Where the value is stored in the input: `Some(true)` for big endian
+    fn in_input(&mut self, value: u32) -> Option<bool> {
+        let input = &self.input;
+        let windows = self.windows.get_or_insert_with(|| {
+            input
+                .windows(4)
+                .map(|window| u32::from_le_bytes(window.try_into().unwrap()))
+                .collect()
+        });
+        if windows.contains(&value) {
+            Some(false)
+        } else if windows.contains(&value.swap_bytes()) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn on_cmp(&mut self, pc: GuestAddr, v0: u32, v1: u32) {
+        if self.suspended || v0 == v1 || v0.count_ones() < MIN_BITS || v1.count_ones() < MIN_BITS {
+            return;
+        }
+        let (stored, computed, big_endian) = match (self.in_input(v0), self.in_input(v1)) {
+            (Some(big_endian), None) => (v0, v1, big_endian),
+            (None, Some(big_endian)) => (v1, v0, big_endian),
+            _ => return,
+        };
+
+        if self.patched.contains(&pc) {
+            let hit = ChecksumHit {
+                pc,
+                stored,
+                computed,
+                big_endian,
+            };
+            if self.hits.len() < MAX_HITS && !self.hits.contains(&hit) {
+                self.hits.push(hit);
+            }
+            return;
+        }
+
+        let exec = self.exec;
+        let site = self.sites.entry(pc).or_default();
+        if site.done || site.last_exec == exec {
+            return;
+        }
+        site.last_exec = exec;
+        site.execs += 1;
+        if site.computed.len() < MIN_EXECS {
+            site.computed.insert(computed);
+        }
+        if site.execs >= MIN_EXECS && site.computed.len() >= MIN_EXECS {
+            site.done = true;
+            self.pending.push(pc);
+        }
+    }
+
+    fn patch(&mut self, qemu: Qemu, pc: GuestAddr) {
+        let Some((addr, patched)) = branch_patch(qemu, pc) else {
+            log::info!("Checksum check @ {pc:#x} has no branch we can patch");
+            return;
+        };
+        if self.branches.contains_key(&addr) {
+            self.patched.insert(pc);
+            return;
+        }
+        let mut original = vec![0; patched.len()];
+        if let Err(e) = qemu.read_mem(addr, &mut original) {
+            log::error!("Failed to read the checksum check @ {pc:#x}: {e:?}");
+            return;
+        }
+        // QEMU writes through read-only code pages and drops their stale translations
+        if let Err(e) = qemu.write_mem(addr, &patched) {
+            log::error!("Failed to patch the checksum check @ {pc:#x}: {e:?}");
+            return;
+        }
+        log::info!("Patched checksum check @ {pc:#x}, branch @ {addr:#x}");
+        exec_event!("checksum", "patch", pc);
+        self.branches.insert(addr, (original, patched));
+        self.patched.insert(pc);
+    }
+
+    /// Put the original branches back for a verification, or the patched ones after it
+    fn suspend(&mut self, qemu: Qemu, suspended: bool) {
+        if self.suspended == suspended {
+            return;
+        }
+        self.suspended = suspended;
+        for (addr, (original, patched)) in &self.branches {
+            let bytes = if suspended { original } else { patched };
+            if let Err(e) = qemu.write_mem(*addr, bytes) {
+                log::error!("Failed to restore the branch @ {addr:#x}: {e:?}");
+            }
+        }
+        qemu.flush_jit();
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for ChecksumPatchModule
+where
+    S: Unpin + HasMetadata,
+    I: Unpin + HasTargetBytes,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        log::debug!("ChecksumPatchModule::first_exec running ...");
+
+        _emulator_modules.cmps(
+            Hook::Function(gen_cmp::<ET, I, S>),
+            Hook::Empty,
+            Hook::Empty,
+            Hook::Function(on_cmp4::<ET, I, S>),
+            Hook::Empty,
+        );
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        let verifying = _state
+            .metadata::<UnpatchedCrashesMeta>()
+            .is_ok_and(UnpatchedCrashesMeta::is_verifying);
+        self.suspend(_qemu, verifying);
+        self.exec += 1;
+        let bytes = _input.target_bytes();
+        self.input = FaultSchedule::split(bytes.as_slice()).0.to_vec();
+        self.windows = None;
+        self.hits.clear();
+    }
+
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        // Not from the comparison hook, the code may be running right now
+        let pending = std::mem::take(&mut self.pending);
+        for pc in &pending {
+            self.patch(_qemu, *pc);
+        }
+        if !pending.is_empty() {
+            _qemu.flush_jit();
+        }
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+fn is_branch(mnemonic: &str) -> bool {
+    #[cfg(feature = "x86_64")]
+    let branch = mnemonic.starts_with('j') || mnemonic == "call" || mnemonic == "ret";
+    #[cfg(feature = "aarch64")]
+    let branch = mnemonic.starts_with('b')
+        || mnemonic.starts_with("cb")
+        || mnemonic.starts_with("tb")
+        || mnemonic == "ret";
+    branch
+}
+
+/// The first conditional branch at or after `pc`, with the bytes that always take the
+/// "equal" path
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+fn branch_patch(qemu: Qemu, pc: GuestAddr) -> Option<(GuestAddr, Vec<u8>)> {
+    let mut code = [0_u8; 32];
+    qemu.read_mem(pc, &mut code).ok()?;
+    let cs = crate::modules::crash_info::disassembler()?;
+    let insns = cs.disasm_count(&code, pc as u64, 4).ok()?;
+
+    for insn in insns.iter() {
+        let bytes = insn.bytes();
+        let mnemonic = insn.mnemonic()?;
+        let patched = match mnemonic {
+            #[cfg(feature = "x86_64")]
+            "jne" => vec![0x90; bytes.len()],
+            #[cfg(feature = "x86_64")]
+            "je" => match bytes {
+                [0x74, rel] => vec![0xeb, *rel],
+                // A nop keeps the end of the instruction, so the rel32 stays the same
+                [0x0f, 0x84, rel @ ..] => [&[0x90, 0xe9][..], rel].concat(),
+                _ => return None,
+            },
+            #[cfg(feature = "aarch64")]
+            "b.ne" => vec![0x1f, 0x20, 0x03, 0xd5],
+            #[cfg(feature = "aarch64")]
+            "b.eq" => {
+                // b.eq and b count their offsets in instructions from the same address
+                let word = u32::from_le_bytes(bytes.try_into().ok()?);
+                let offset = (((word >> 5) & 0x7ffff) as i32) << 13 >> 13;
+                (0x1400_0000 | (offset as u32 & 0x03ff_ffff)).to_le_bytes().to_vec()
+            }
+            mnemonic if is_branch(mnemonic) => return None,
+            _ => continue,
+        };
+        return Some((insn.address() as GuestAddr, patched));
+    }
+    None
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+fn branch_patch(_qemu: Qemu, _pc: GuestAddr) -> Option<(GuestAddr, Vec<u8>)> {
+    None
+}
+
+/// Only 4-byte comparisons get an id, checksums are 32 bits
+fn gen_cmp<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+    size: usize,
+) -> Option<u64>
+where
+    S: Unpin + HasMetadata,
+    I: Unpin + HasTargetBytes,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    (size == 4).then_some(pc as u64)
+}
+
+fn on_cmp4<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    id: u64,
+    v0: u32,
+    v1: u32,
+) where
+    S: Unpin + HasMetadata,
+    I: Unpin + HasTargetBytes,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    emulator_modules
+        .get_mut::<ChecksumPatchModule>()
+        .expect("Failed to get ChecksumPatchModule")
+        .on_cmp(id as GuestAddr, v0, v1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(stored: u32, computed: u32, big_endian: bool) -> ChecksumHit {
+        ChecksumHit {
+            pc: 0x1000,
+            stored,
+            computed,
+            big_endian,
+        }
+    }
+
+    #[test]
+    fn fix() {
+        let mut bytes = b"IHDR\x12\x34\x56\x78data\x12\x34\x56\x78".to_vec();
+        hit(0x1234_5678, 0xdead_beef, true).fix(&mut bytes);
+        assert_eq!(bytes, b"IHDR\xde\xad\xbe\xefdata\xde\xad\xbe\xef");
+
+        let mut bytes = b"\x78\x56\x34\x12tail".to_vec();
+        hit(0x1234_5678, 0xdead_beef, false).fix(&mut bytes);
+        assert_eq!(bytes, b"\xef\xbe\xad\xdetail");
+
+        // Byte order has to match, and a short input holds no value at all
+        let mut bytes = b"\x12\x34\x56\x78".to_vec();
+        hit(0x1234_5678, 0xdead_beef, false).fix(&mut bytes);
+        assert_eq!(bytes, b"\x12\x34\x56\x78");
+        let mut bytes = b"\x12\x34\x56".to_vec();
+        hit(0x1234_5678, 0xdead_beef, true).fix(&mut bytes);
+        assert_eq!(bytes, b"\x12\x34\x56");
+    }
+
+    #[test]
+    fn unpatched_crashes() {
+        let mut meta = UnpatchedCrashesMeta::default();
+        let hits = vec![hit(1, 2, false)];
+        assert!(meta.push_pending(b"a".to_vec(), hits.clone()));
+        assert!(!meta.push_pending(b"a".to_vec(), vec![]));
+        for idx in 1..MAX_PENDING_CRASHES {
+            assert!(meta.push_pending(vec![0, idx as u8], vec![]));
+        }
+        assert!(!meta.push_pending(b"b".to_vec(), vec![]));
+
+        assert_eq!(meta.pop_pending(), Some((b"a".to_vec(), hits.clone())));
+        assert!(meta.push_pending(b"b".to_vec(), vec![]));
+
+        assert!(!meta.is_verifying());
+        meta.set_verifying(Some(hits.clone()));
+        assert!(meta.is_verifying());
+        assert_eq!(meta.take_verifying(), Some(hits));
+        assert!(!meta.is_verifying());
+        assert_eq!(meta.take_verifying(), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Value profile of the comparisons of the target, on every core.
+//!
+//! For each comparison site, the execution records the most operand bits that were equal in
+//! any of its comparisons. Unlike the cmplog tracing, which only the cmplog cores run on a
+//! few inputs, this is cheap enough to run on every execution: `CmpCoverageFeedback` uses it
+//! to keep inputs that get closer to a magic value, bit by bit.
+use libafl::{executors::ExitKind, observers::ObserversTuple, HasMetadata};
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    EmulatorModules, GuestAddr, Hook, Qemu,
+};
+use serde::{Deserialize, Serialize};
+
+/// Comparison sites are hashed into this many slots
+pub const CMP_MAP_SIZE: usize = 1 << 16;
+
+/// The comparison sites of the current execution, with their most equal operand bits
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CmpCoverageMeta {
+    pub sites: Vec<(u32, u8)>,
+}
+
+libafl_bolts::impl_serdeany!(CmpCoverageMeta);
+
+#[derive(Debug)]
+pub struct CmpCoverageModule {
+    enabled: bool,
+    map: Vec<u8>,
+    /// The slots of `map` set in the current execution
+    touched: Vec<u32>,
+}
+
+impl CmpCoverageModule {
+    /// A disabled module doesn't install any hook
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            map: if enabled { vec![0; CMP_MAP_SIZE] } else { Vec::new() },
+            touched: Vec::new(),
+        }
+    }
+
+    fn on_cmp(&mut self, slot: u64, equal_bits: u32) {
+        let slot = slot as u32;
+        let best = &mut self.map[slot as usize];
+        if *best == 0 {
+            self.touched.push(slot);
+        }
+        // 0 marks an untouched slot
+        *best = (*best).max(equal_bits as u8 + 1);
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for CmpCoverageModule
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        log::debug!("CmpCoverageModule::first_exec running ...");
+
+        // Single byte comparisons are left to havoc
+        _emulator_modules.cmps(
+            Hook::Function(gen_cmp::<ET, I, S>),
+            Hook::Empty,
+            Hook::Function(on_cmp2::<ET, I, S>),
+            Hook::Function(on_cmp4::<ET, I, S>),
+            Hook::Function(on_cmp8::<ET, I, S>),
+        );
+        _state.add_metadata(CmpCoverageMeta::default());
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        for slot in self.touched.drain(..) {
+            self.map[slot as usize] = 0;
+        }
+        if let Ok(meta) = _state.metadata_mut::<CmpCoverageMeta>() {
+            meta.sites.clear();
+        }
+    }
+
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        if let Ok(meta) = _state.metadata_mut::<CmpCoverageMeta>() {
+            meta.sites.extend(
+                self.touched
+                    .iter()
+                    .map(|slot| (*slot, self.map[*slot as usize] - 1)),
+            );
+        }
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+/// The id of a comparison is its slot in the map
+fn gen_cmp<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+    size: usize,
+) -> Option<u64>
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let pc = pc as u64;
+    (size >= 2).then_some((pc ^ (pc >> 16)) & (CMP_MAP_SIZE as u64 - 1))
+}
+
+macro_rules! on_cmp {
+    ($name:ident, $ty:ty) => {
+        fn $name<ET, I, S>(
+            _qemu: Qemu,
+            emulator_modules: &mut EmulatorModules<ET, I, S>,
+            _state: Option<&mut S>,
+            id: u64,
+            v0: $ty,
+            v1: $ty,
+        ) where
+            S: Unpin + HasMetadata,
+            I: Unpin,
+            ET: EmulatorModuleTuple<I, S>,
+        {
+            emulator_modules
+                .get_mut::<CmpCoverageModule>()
+                .expect("Failed to get CmpCoverageModule")
+                .on_cmp(id, (v0 ^ v1).count_zeros());
+        }
+    };
+}
+
+on_cmp!(on_cmp2, u16);
+on_cmp!(on_cmp4, u32);
+on_cmp!(on_cmp8, u64);
@@ -0,0 +1,405 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    EmulatorModules, GuestAddr, GuestReg, Qemu, Regs,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modules::{checksum_patch::ChecksumHit, AllocFailureModule, ChecksumPatchModule},
+    trace,
+};
+
+/// Frames [`backtrace`] follows at most
+const MAX_FRAMES: usize = 16;
+
+/// The guest crash of the current execution.
+/// Written from QEMU's crash hook, taken by the `CrashInfoFeedback` in the crash handler.
+static LAST_CRASH: Mutex<Option<CrashInfo>> = Mutex::new(None);
+
+/// Details about a guest crash, attached to the testcase in the solutions.
+/// Addresses and registers are kept as hex strings to make the metadata file readable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashInfo {
+    pub signal: i32,
+    pub signal_name: String,
+    pub pc: String,
+    /// The faulting address (`si_addr`) for SIGSEGV / SIGBUS, if it could be recovered
+    pub fault_addr: Option<String>,
+    pub registers: BTreeMap<String, String>,
+    /// The return addresses on the guest stack, innermost first, see [`backtrace`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backtrace: Vec<String>,
+    /// The last execution events before the crash, see `--exec-ring`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recent_events: Vec<String>,
+    /// Indices of the allocations failed by `--alloc-failure` before the crash
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_allocations: Vec<usize>,
+    /// Checksum checks patched by `--checksum-patch` the crashing execution went through
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checksum_patches: Vec<ChecksumHit>,
+}
+
+libafl_bolts::impl_serdeany!(CrashInfo);
+
+/// The signal and the PC (as stored in [`CrashInfo::pc`]) of a crash
+pub type CrashSignature = (i32, String);
+
+impl CrashInfo {
+    fn capture(qemu: Qemu, signal: i32) -> Self {
+        let regs = named_registers(qemu);
+        let pc: GuestReg = qemu.read_reg(Regs::Pc).unwrap_or_default();
+
+        let fault_addr = match signal {
+            libc::SIGSEGV | libc::SIGBUS => fault_addr(qemu, pc as GuestAddr, &regs),
+            _ => None,
+        };
+
+        Self {
+            signal,
+            signal_name: signal_name(signal),
+            pc: format!("{pc:#x}"),
+            fault_addr: fault_addr.map(|addr| format!("{addr:#x}")),
+            registers: regs
+                .into_iter()
+                .map(|(name, value)| (name, format!("{value:#x}")))
+                .collect(),
+            backtrace: backtrace(qemu)
+                .into_iter()
+                .map(|addr| format!("{addr:#x}"))
+                .collect(),
+            recent_events: trace::dump(),
+            failed_allocations: Vec::new(),
+            checksum_patches: Vec::new(),
+        }
+    }
+
+    /// Where the target crashed, to tell bugs apart without the input. `abort()` always
+    /// crashes at the same PC in libc, so aborts have no signature.
+    pub fn signature(&self) -> Option<CrashSignature> {
+        (self.signal != libc::SIGABRT).then(|| (self.signal, self.pc.clone()))
+    }
+
+    /// The info about the guest crash of the current execution, if there was one
+    pub fn peek() -> Option<Self> {
+        LAST_CRASH.lock().ok()?.clone()
+    }
+
+    /// Take the info about the guest crash of the current execution, if there was one
+    pub fn take() -> Option<Self> {
+        LAST_CRASH.lock().ok()?.take()
+    }
+}
+
+/// All guest registers by their lowercase name, which is also how capstone names them
+pub fn named_registers(qemu: Qemu) -> BTreeMap<String, GuestReg> {
+    let mut regs = BTreeMap::new();
+    for idx in 0..qemu.num_regs() {
+        if let Ok(value) = qemu.read_reg(idx) {
+            let name = Regs::try_from(idx)
+                .map_or_else(|_| format!("r{idx}"), |reg| format!("{reg:?}").to_lowercase());
+            regs.insert(name, value);
+        }
+    }
+    regs
+}
+
+/// The return addresses of the guest call stack, following the saved frame pointers. Code built
+/// without frame pointers cuts the walk short, it still ends the same way for the same crash.
+pub fn backtrace(qemu: Qemu) -> Vec<GuestAddr> {
+    #[cfg(feature = "x86_64")]
+    let fp = Regs::Rbp;
+    #[cfg(feature = "aarch64")]
+    let fp = Regs::Fp;
+    #[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+    {
+        let _ = qemu;
+        Vec::new()
+    }
+
+    #[cfg(any(feature = "x86_64", feature = "aarch64"))]
+    {
+        const WORD: usize = size_of::<GuestAddr>();
+
+        let mut frames = Vec::new();
+        let mut fp = qemu.read_reg(fp).map_or(0, |fp: GuestReg| fp as GuestAddr);
+        while frames.len() < MAX_FRAMES && fp != 0 {
+            // The saved frame pointer of the caller, then the return address
+            let mut record = [0_u8; 2 * WORD];
+            if qemu.read_mem(fp, &mut record).is_err() {
+                break;
+            }
+            let next = GuestAddr::from_le_bytes(record[..WORD].try_into().unwrap());
+            let ret = GuestAddr::from_le_bytes(record[WORD..].try_into().unwrap());
+            if ret == 0 {
+                break;
+            }
+            frames.push(ret);
+            // The frames of the callers are further up the stack
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+        frames
+    }
+}
+
+/// A capstone instance with details enabled for the guest architecture
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+pub fn disassembler() -> Option<capstone::Capstone> {
+    use capstone::prelude::*;
+
+    #[cfg(feature = "x86_64")]
+    let cs = Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .detail(true)
+        .build();
+    #[cfg(feature = "aarch64")]
+    let cs = Capstone::new()
+        .arm64()
+        .mode(arch::arm64::ArchMode::Arm)
+        .detail(true)
+        .build();
+    cs.ok()
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        libc::SIGSEGV => "SIGSEGV".to_string(),
+        libc::SIGBUS => "SIGBUS".to_string(),
+        libc::SIGILL => "SIGILL".to_string(),
+        libc::SIGFPE => "SIGFPE".to_string(),
+        libc::SIGABRT => "SIGABRT".to_string(),
+        libc::SIGTRAP => "SIGTRAP".to_string(),
+        _ => format!("signal {signal}"),
+    }
+}
+
+/// qemu-user doesn't hand the guest `siginfo_t` to the crash hook, so recover `si_addr` from
+/// the memory operand of the faulting instruction. If the instruction itself can't be read,
+/// the fault happened while fetching it.
+#[cfg(any(feature = "x86_64", feature = "aarch64"))]
+fn fault_addr(qemu: Qemu, pc: GuestAddr, regs: &BTreeMap<String, GuestReg>) -> Option<GuestAddr> {
+    use capstone::prelude::*;
+
+    let mut code = [0_u8; 16];
+    if qemu.read_mem(pc, &mut code).is_err() {
+        return Some(pc);
+    }
+
+    let cs = disassembler()?;
+
+    let insns = cs.disasm_count(&code, pc as u64, 1).ok()?;
+    let insn = insns.first()?;
+    let detail = cs.insn_detail(insn).ok()?;
+    let reg = |id: RegId| -> Option<GuestReg> {
+        if id == RegId::INVALID_REG {
+            Some(0)
+        } else {
+            regs.get(&cs.reg_name(id)?).copied()
+        }
+    };
+
+    let operands = detail.arch_detail().operands();
+    for op in &operands {
+        #[cfg(feature = "x86_64")]
+        if let arch::ArchOperand::X86Operand(arch::x86::X86Operand {
+            op_type: arch::x86::X86OperandType::Mem(mem),
+            ..
+        }) = op
+        {
+            let base = if mem.base() == RegId(arch::x86::X86Reg::X86_REG_RIP as RegIdInt) {
+                pc + insn.len() as GuestAddr
+            } else {
+                reg(mem.base())?
+            };
+            let index = reg(mem.index())?;
+            let addr = base
+                .wrapping_add(index.wrapping_mul(mem.scale() as GuestReg))
+                .wrapping_add(mem.disp() as GuestReg);
+            return Some(addr);
+        }
+
+        #[cfg(feature = "aarch64")]
+        if let arch::ArchOperand::Arm64Operand(arch::arm64::Arm64Operand {
+            op_type: arch::arm64::Arm64OperandType::Mem(mem),
+            shift,
+            ext,
+            ..
+        }) = op
+        {
+            use arch::arm64::{Arm64Extender, Arm64Shift};
+
+            let base = reg(mem.base())?;
+            // Post-indexed accesses (`[x1], #8`) have the offset as operand after the memory
+            // operand, and access the base before it is written back
+            if !std::ptr::eq(op, operands.last()?) {
+                return Some(base);
+            }
+
+            let index = mem.index();
+            if index == RegId::INVALID_REG {
+                // Immediate offsets, pre-indexed ones (`[x1, #8]!`) access the written back address
+                return Some(base.wrapping_add(mem.disp() as GuestReg));
+            }
+            // A 32-bit index is read from its 64-bit register and extended below
+            let name = cs.reg_name(index)?;
+            let index = match name.strip_prefix('w') {
+                Some("zr") => 0,
+                Some(num) => regs.get(&format!("x{num}")).copied()?,
+                None if name == "xzr" => 0,
+                None => regs.get(&name).copied()?,
+            };
+            let extend = match ext {
+                Arm64Extender::ARM64_EXT_INVALID
+                | Arm64Extender::ARM64_EXT_UXTX
+                | Arm64Extender::ARM64_EXT_SXTX => IndexExtend::None,
+                Arm64Extender::ARM64_EXT_UXTW => IndexExtend::Uxtw,
+                Arm64Extender::ARM64_EXT_SXTW => IndexExtend::Sxtw,
+                _ => return None,
+            };
+            let shift = match shift {
+                Arm64Shift::Invalid => 0,
+                Arm64Shift::Lsl(amount) => *amount,
+                _ => return None,
+            };
+            return indexed_addr(base, index, extend, shift, mem.disp().into());
+        }
+    }
+    None
+}
+
+/// How an aarch64 index register is extended before it is shifted
+#[cfg(any(feature = "aarch64", test))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexExtend {
+    None,
+    /// Zero-extend the low 32 bits
+    Uxtw,
+    /// Sign-extend the low 32 bits
+    Sxtw,
+}
+
+/// The address of an aarch64 memory operand `[base, index, extend #shift]`. Register offsets
+/// come without an immediate, and are shifted by at most the access size (16 bytes).
+#[cfg(any(feature = "aarch64", test))]
+fn indexed_addr(base: u64, index: u64, extend: IndexExtend, shift: u32, disp: i64) -> Option<u64> {
+    if disp != 0 || shift > 4 {
+        return None;
+    }
+    let index = match extend {
+        IndexExtend::None => index,
+        IndexExtend::Uxtw => u64::from(index as u32),
+        IndexExtend::Sxtw => i64::from(index as i32) as u64,
+    };
+    Some(base.wrapping_add(index << shift))
+}
+
+#[cfg(not(any(feature = "x86_64", feature = "aarch64")))]
+fn fault_addr(
+    _qemu: Qemu,
+    _pc: GuestAddr,
+    _regs: &BTreeMap<String, GuestReg>,
+) -> Option<GuestAddr> {
+    None
+}
+
+/// Records the guest signal QEMU is about to die from, with the CPU state at that point.
+/// The crash itself is still reported as `ExitKind::Crash` by the executor's crash handler.
+#[derive(Default, Debug)]
+pub struct CrashInfoModule;
+
+impl CrashInfoModule {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for CrashInfoModule
+where
+    S: Unpin,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    /// Crash hooks run in the order they are registered and the one of `QemuExecutor::new`
+    /// never returns, so ours have to be registered before the executor is built
+    fn post_qemu_init<ET>(&mut self, _qemu: Qemu, _emulator_modules: &mut EmulatorModules<ET, I, S>)
+    where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        log::debug!("CrashInfoModule::post_qemu_init running ...");
+        _emulator_modules.crash_function(on_guest_crash::<ET, I, S>);
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if let Ok(mut last_crash) = LAST_CRASH.lock() {
+            *last_crash = None;
+        }
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+fn on_guest_crash<ET, I, S>(qemu: Qemu, emulator_modules: &mut EmulatorModules<ET, I, S>, target_signal: i32)
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let mut crash_info = CrashInfo::capture(qemu, target_signal);
+    if let Some(module) = emulator_modules.get_mut::<AllocFailureModule>() {
+        crash_info.failed_allocations = module.failed().to_vec();
+    }
+    if let Some(module) = emulator_modules.get_mut::<ChecksumPatchModule>() {
+        crash_info.checksum_patches = module.hits().to_vec();
+    }
+    if let Ok(mut last_crash) = LAST_CRASH.lock() {
+        *last_crash = Some(crash_info);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexed_addresses() {
+        // ldr x0, [x1, x2, lsl #3]
+        assert_eq!(indexed_addr(0x1000, 2, IndexExtend::None, 3, 0), Some(0x1010));
+        // ldrb w0, [x1, x2]
+        assert_eq!(indexed_addr(0x1000, 0x10, IndexExtend::None, 0, 0), Some(0x1010));
+        // ldr w0, [x1, w2, uxtw #2], the upper half of x2 is ignored
+        assert_eq!(
+            indexed_addr(0x1000, 0xffff_ffff_0000_0004, IndexExtend::Uxtw, 2, 0),
+            Some(0x1010)
+        );
+        // ldr x0, [x1, w2, sxtw #3] with w2 = -1
+        assert_eq!(
+            indexed_addr(0x1000, 0xffff_ffff, IndexExtend::Sxtw, 3, 0),
+            Some(0xff8)
+        );
+        assert_eq!(indexed_addr(0x1000, 0x7fff_ffff, IndexExtend::Sxtw, 0, 0), Some(0x8000_0fff));
+        // Index and immediate together, and out of range shifts, aren't addressing modes
+        assert_eq!(indexed_addr(0x1000, 1, IndexExtend::None, 0, 8), None);
+        assert_eq!(indexed_addr(0x1000, 1, IndexExtend::None, 5, 0), None);
+    }
+}
@@ -0,0 +1,345 @@
+//! A trace of a single (replayed) execution: every executed basic block and optionally
+//! memory accesses and syscalls, in execution order.
+//!
+//! Records are streamed to the file and flushed after the execution or on a guest crash,
+//! so the traces of a crashing input and its parent can be diffed directly.
+//! The binary format is a sequence of records, each a tag byte followed by little endian
+//! `u64` fields:
+//! - `0` block: `pc`
+//! - `1` read / `2` write: `pc`, `addr`, `size`
+//! - `3` syscall: `nr`, `a0` .. `a5`, `ret`
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use libafl::{executors::ExitKind, observers::ObserversTuple, Error};
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    sys::TCGTemp,
+    EmulatorModules, GuestAddr, Hook, MemAccessInfo, Qemu,
+};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// Compact tagged records, see the module documentation
+    Binary,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum TraceEvent {
+    Block {
+        pc: u64,
+    },
+    Read {
+        pc: u64,
+        addr: u64,
+        size: u64,
+    },
+    Write {
+        pc: u64,
+        addr: u64,
+        size: u64,
+    },
+    Syscall {
+        nr: u64,
+        args: [u64; 6],
+        ret: u64,
+    },
+}
+
+impl TraceEvent {
+    fn write_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut fields = [0_u64; 8];
+        let (tag, len) = match *self {
+            Self::Block { pc } => {
+                fields[0] = pc;
+                (0_u8, 1)
+            }
+            Self::Read { pc, addr, size } => {
+                fields[..3].copy_from_slice(&[pc, addr, size]);
+                (1, 3)
+            }
+            Self::Write { pc, addr, size } => {
+                fields[..3].copy_from_slice(&[pc, addr, size]);
+                (2, 3)
+            }
+            Self::Syscall { nr, args, ret } => {
+                fields[0] = nr;
+                fields[1..7].copy_from_slice(&args);
+                fields[7] = ret;
+                (3, 8)
+            }
+        };
+        writer.write_all(&[tag])?;
+        for field in &fields[..len] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ExecTraceModule {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    mem: bool,
+    syscalls: bool,
+    failed: bool,
+}
+
+impl ExecTraceModule {
+    pub fn new(path: &Path, format: TraceFormat, mem: bool, syscalls: bool) -> Result<Self, Error> {
+        let file = File::create(path)
+            .map_err(|e| Error::illegal_argument(format!("Failed to create trace {path:?}: {e}")))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            format,
+            mem,
+            syscalls,
+            failed: false,
+        })
+    }
+
+    fn record(&mut self, event: &TraceEvent) {
+        if self.failed {
+            return;
+        }
+        let result = match self.format {
+            TraceFormat::Jsonl => serde_json::to_writer(&mut self.writer, event)
+                .map_err(io::Error::from)
+                .and_then(|()| self.writer.write_all(b"\n")),
+            TraceFormat::Binary => event.write_binary(&mut self.writer),
+        };
+        if let Err(e) = result {
+            // Don't spam the log for every following block
+            log::error!("Failed to write the execution trace: {e}");
+            self.failed = true;
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            log::error!("Failed to flush the execution trace: {e}");
+        }
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for ExecTraceModule
+where
+    S: Unpin,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    /// Before the crash hook of the executor, see `CrashInfoModule::post_qemu_init`
+    fn post_qemu_init<ET>(&mut self, _qemu: Qemu, _emulator_modules: &mut EmulatorModules<ET, I, S>)
+    where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        _emulator_modules.crash_function(flush_on_crash::<ET, I, S>);
+    }
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        log::debug!("ExecTraceModule::first_exec running ...");
+
+        _emulator_modules.blocks(
+            Hook::Function(gen_block::<ET, I, S>),
+            Hook::Empty,
+            Hook::Function(trace_block::<ET, I, S>),
+        );
+        if self.mem {
+            _emulator_modules.reads(
+                Hook::Function(gen_access::<ET, I, S>),
+                Hook::Function(trace_access::<ET, I, S, false, 1>),
+                Hook::Function(trace_access::<ET, I, S, false, 2>),
+                Hook::Function(trace_access::<ET, I, S, false, 4>),
+                Hook::Function(trace_access::<ET, I, S, false, 8>),
+                Hook::Function(trace_access_n::<ET, I, S, false>),
+            );
+            _emulator_modules.writes(
+                Hook::Function(gen_access::<ET, I, S>),
+                Hook::Function(trace_access::<ET, I, S, true, 1>),
+                Hook::Function(trace_access::<ET, I, S, true, 2>),
+                Hook::Function(trace_access::<ET, I, S, true, 4>),
+                Hook::Function(trace_access::<ET, I, S, true, 8>),
+                Hook::Function(trace_access_n::<ET, I, S, true>),
+            );
+        }
+        if self.syscalls {
+            _emulator_modules.post_syscalls(Hook::Function(trace_syscall::<ET, I, S>));
+        }
+
+        // Blocks translated before the hooks were installed would not be traced
+        _qemu.flush_jit();
+    }
+
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        self.flush();
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+fn trace_module<ET, I, S>(emulator_modules: &mut EmulatorModules<ET, I, S>) -> &mut ExecTraceModule
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    emulator_modules
+        .get_mut::<ExecTraceModule>()
+        .expect("Failed to get ExecTraceModule")
+}
+
+/// The block id passed to the exec hook is its address
+fn gen_block<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+) -> Option<u64>
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    Some(pc as u64)
+}
+
+fn trace_block<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    id: u64,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    trace_module(emulator_modules).record(&TraceEvent::Block { pc: id });
+}
+
+/// The access id passed to the exec hooks is the address of the accessing instruction
+fn gen_access<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+    _addr: *mut TCGTemp,
+    _info: MemAccessInfo,
+) -> Option<u64>
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    Some(pc as u64)
+}
+
+fn trace_access<ET, I, S, const WRITE: bool, const SIZE: usize>(
+    qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    state: Option<&mut S>,
+    id: u64,
+    addr: GuestAddr,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    trace_access_n::<ET, I, S, WRITE>(qemu, emulator_modules, state, id, addr, SIZE);
+}
+
+fn trace_access_n<ET, I, S, const WRITE: bool>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    id: u64,
+    addr: GuestAddr,
+    size: usize,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let (pc, addr, size) = (id, addr as u64, size as u64);
+    let event = if WRITE {
+        TraceEvent::Write { pc, addr, size }
+    } else {
+        TraceEvent::Read { pc, addr, size }
+    };
+    trace_module(emulator_modules).record(&event);
+}
+
+/// Runs after the `InputInjectorModule` hooks, so injected results are recorded as well
+#[expect(clippy::too_many_arguments)]
+fn trace_syscall<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    result: GuestAddr,
+    sys_num: i32,
+    a0: GuestAddr,
+    a1: GuestAddr,
+    a2: GuestAddr,
+    a3: GuestAddr,
+    a4: GuestAddr,
+    a5: GuestAddr,
+    _a6: GuestAddr,
+    _a7: GuestAddr,
+) -> GuestAddr
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    trace_module(emulator_modules).record(&TraceEvent::Syscall {
+        nr: sys_num as u64,
+        args: [a0, a1, a2, a3, a4, a5].map(|arg| arg as u64),
+        ret: result as u64,
+    });
+    result
+}
+
+fn flush_on_crash<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _target_signal: i32,
+) where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    trace_module(emulator_modules).flush();
+}
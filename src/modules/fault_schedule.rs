@@ -0,0 +1,185 @@
+//! Syscall faults encoded at the end of the fuzz input.
+//!
+//! With fault injection enabled, an input may end with a trailer
+//! `[index (u16 LE), fault] * count || count || MAGIC`: the `index`-th faultable syscall of
+//! the execution (reads of the input, anonymous mmaps and openat, counted together) fails
+//! as described by `fault`. Indices with [`ALLOC_FLAG`] set fail the `index & 0x7fff`-th
+//! allocation of the target instead, see `AllocFailureModule`.
+//! Inputs without the trailer run without faults.
+use libafl_qemu::GuestAddr;
+use serde::{Deserialize, Serialize};
+
+pub const MAGIC: &[u8; 4] = b"FLT\xfb";
+
+/// Marks the index of an entry as allocation index
+pub const ALLOC_FLAG: u16 = 0x8000;
+
+/// The highest syscall or allocation index an entry can hold
+pub const MAX_INDEX: u16 = !ALLOC_FLAG;
+
+/// Bytes of an entry in the trailer
+const ENTRY_SIZE: usize = 3;
+
+/// Most entries a schedule can hold
+pub const MAX_FAULTS: usize = u8::MAX as usize;
+
+const READ_ERRNOS: [i32; 3] = [libc::EINTR, libc::EIO, libc::EAGAIN];
+const OPEN_ERRNOS: [i32; 4] = [libc::ENOENT, libc::EACCES, libc::EMFILE, libc::EINTR];
+
+/// What an intercepted read returns instead of the requested bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFault {
+    /// At most this many bytes
+    Short(usize),
+    Errno(i32),
+}
+
+/// The most faultable syscalls and allocations a single execution made so far, the indices
+/// the `FaultScheduleMutator` picks from
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FaultCountsMeta {
+    pub syscalls: usize,
+    pub allocs: usize,
+}
+
+libafl_bolts::impl_serdeany!(FaultCountsMeta);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultSchedule {
+    entries: Vec<(u16, u8)>,
+}
+
+impl FaultSchedule {
+    /// Split `bytes` into the input data and its schedule
+    pub fn split(bytes: &[u8]) -> (&[u8], Self) {
+        let Some(rest) = bytes.strip_suffix(MAGIC) else {
+            return (bytes, Self::default());
+        };
+        let Some((count, rest)) = rest.split_last() else {
+            return (bytes, Self::default());
+        };
+        let len = *count as usize * ENTRY_SIZE;
+        if rest.len() < len {
+            return (bytes, Self::default());
+        }
+
+        let (data, entries) = rest.split_at(rest.len() - len);
+        let entries = entries
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| (u16::from_le_bytes([entry[0], entry[1]]), entry[2]))
+            .collect();
+        (data, Self { entries })
+    }
+
+    /// Append the schedule as trailer to `data`
+    pub fn join(&self, data: &[u8]) -> Vec<u8> {
+        let mut bytes = data.to_vec();
+        for (index, fault) in &self.entries {
+            bytes.extend_from_slice(&index.to_le_bytes());
+            bytes.push(*fault);
+        }
+        bytes.push(self.entries.len() as u8);
+        bytes.extend_from_slice(MAGIC);
+        bytes
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<(u16, u8)> {
+        &mut self.entries
+    }
+
+    /// The fault of the `index`-th faultable syscall, if it should fail
+    pub fn fault(&self, index: usize) -> Option<u8> {
+        self.entries
+            .iter()
+            .find(|(idx, _)| idx & ALLOC_FLAG == 0 && *idx as usize == index)
+            .map(|(_, fault)| *fault)
+    }
+
+    /// Whether the `index`-th allocation should fail
+    pub fn alloc_fault(&self, index: usize) -> bool {
+        self.entries
+            .iter()
+            .any(|(idx, _)| idx & ALLOC_FLAG != 0 && (idx & !ALLOC_FLAG) as usize == index)
+    }
+
+    /// The low bit picks a short read (of up to 128 bytes) or an errno
+    pub fn read_fault(fault: u8) -> ReadFault {
+        if fault & 1 == 0 {
+            ReadFault::Short((fault >> 1) as usize + 1)
+        } else {
+            ReadFault::Errno(READ_ERRNOS[(fault >> 1) as usize % READ_ERRNOS.len()])
+        }
+    }
+
+    pub fn open_errno(fault: u8) -> i32 {
+        OPEN_ERRNOS[fault as usize % OPEN_ERRNOS.len()]
+    }
+
+    /// The syscall return value for `errno`
+    pub fn error(errno: i32) -> GuestAddr {
+        (-i64::from(errno)) as GuestAddr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut schedule = FaultSchedule::default();
+        schedule.entries_mut().extend([(0, 7), (3, 1)]);
+        let bytes = schedule.join(b"data");
+
+        let (data, parsed) = FaultSchedule::split(&bytes);
+        assert_eq!(data, b"data");
+        assert_eq!(parsed, schedule);
+        assert_eq!(parsed.fault(0), Some(7));
+        assert_eq!(parsed.fault(3), Some(1));
+        assert_eq!(parsed.fault(1), None);
+    }
+
+    #[test]
+    fn inputs_without_schedule() {
+        let (data, schedule) = FaultSchedule::split(b"plain input");
+        assert_eq!(data, b"plain input");
+        assert_eq!(schedule, FaultSchedule::default());
+
+        // More entries than the input holds
+        let bytes = [b"\x01\x00".as_slice(), &[2], MAGIC].concat();
+        let (data, schedule) = FaultSchedule::split(&bytes);
+        assert_eq!(data, bytes);
+        assert_eq!(schedule, FaultSchedule::default());
+
+        // An empty schedule
+        let bytes = FaultSchedule::default().join(b"");
+        assert_eq!(FaultSchedule::split(&bytes), (&b""[..], FaultSchedule::default()));
+    }
+
+    #[test]
+    fn faults() {
+        assert_eq!(FaultSchedule::read_fault(0), ReadFault::Short(1));
+        assert_eq!(FaultSchedule::read_fault(254), ReadFault::Short(128));
+        assert_eq!(FaultSchedule::read_fault(1), ReadFault::Errno(libc::EINTR));
+        assert_eq!(FaultSchedule::open_errno(1), libc::EACCES);
+        assert_eq!(FaultSchedule::error(libc::ENOMEM), (-12_i64) as GuestAddr);
+    }
+    #[test]
+    fn wide_and_allocation_indices() {
+        let mut schedule = FaultSchedule::default();
+        schedule
+            .entries_mut()
+            .extend([(300, 1), (ALLOC_FLAG | 2, 0), (ALLOC_FLAG | MAX_INDEX, 0)]);
+        let bytes = schedule.join(b"data");
+        assert_eq!(bytes.len(), 4 + 3 * ENTRY_SIZE + 1 + MAGIC.len());
+
+        let (_, parsed) = FaultSchedule::split(&bytes);
+        assert_eq!(parsed, schedule);
+        assert_eq!(parsed.fault(300), Some(1));
+        // Allocation entries never fail syscalls, and the other way around
+        assert_eq!(parsed.fault(2), None);
+        assert!(parsed.alloc_fault(2));
+        assert!(parsed.alloc_fault(MAX_INDEX as usize));
+        assert!(!parsed.alloc_fault(300));
+    }
+}
@@ -0,0 +1,121 @@
+//! The guest block a hang is stuck in.
+//!
+//! Under TCG, the PC register is only written back when the translated code leaves a chain of
+//! blocks, so reading it from the timeout handler gives a stale value. While a timeout is
+//! verified by the `VerifyHangsStage`, every executed block stores its address instead, and
+//! the `HangFeedback` takes the last one as the key of the hang. Fuzzing runs don't pay for
+//! the hook: blocks are only instrumented while verifying, the JIT is flushed on the switch.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use libafl::HasMetadata;
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    EmulatorModules, GuestAddr, Hook, Qemu,
+};
+
+use crate::feedbacks::hang::HangsMeta;
+
+/// The last block the current execution entered, 0 if blocks aren't tracked
+static LAST_BLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// The last block the current execution entered, if blocks are tracked
+pub fn last_block() -> Option<GuestAddr> {
+    match LAST_BLOCK.load(Ordering::Relaxed) {
+        0 => None,
+        pc => Some(pc as GuestAddr),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct HangPcModule {
+    tracking: bool,
+}
+
+impl HangPcModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for HangPcModule
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        log::debug!("HangPcModule::first_exec running ...");
+        _emulator_modules.blocks(
+            Hook::Function(gen_block::<ET, I, S>),
+            Hook::Empty,
+            Hook::Function(on_block::<ET, I, S>),
+        );
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        let verifying = _state
+            .metadata::<HangsMeta>()
+            .is_ok_and(HangsMeta::is_verifying);
+        if verifying != self.tracking {
+            self.tracking = verifying;
+            // Blocks are instrumented when they are translated
+            _qemu.flush_jit();
+        }
+        LAST_BLOCK.store(0, Ordering::Relaxed);
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+/// Only blocks translated while verifying get the exec hook
+fn gen_block<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    pc: GuestAddr,
+) -> Option<u64>
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let tracking = emulator_modules
+        .get_mut::<HangPcModule>()
+        .is_some_and(|module| module.tracking);
+    tracking.then_some(pc as u64)
+}
+
+fn on_block<ET, I, S>(
+    _qemu: Qemu,
+    _emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    id: u64,
+) where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    LAST_BLOCK.store(id, Ordering::Relaxed);
+}
@@ -1,18 +1,31 @@
 use std::process::abort;
 
-use libafl::{inputs::HasTargetBytes, HasMetadata};
+use libafl::{executors::ExitKind, inputs::HasTargetBytes, observers::ObserversTuple, HasMetadata};
 use libafl_qemu::{
-    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple}, EmulatorModules, GuestAddr, Hook, Qemu, SYS_exit, SYS_exit_group, SYS_mmap, SYS_munmap, SYS_read, SyscallHookResult
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple}, EmulatorModules, GuestAddr, Hook, Qemu, Regs, SYS_exit, SYS_exit_group, SYS_mmap, SYS_munmap, SYS_openat, SYS_read, SyscallHookResult
 };
 
-use crate::modules::ExecMeta;
+use crate::{
+    modules::{
+        fault_schedule::{FaultCountsMeta, FaultSchedule, ReadFault},
+        ExecMeta,
+    },
+    trace::exec_event,
+};
 
 #[derive(Default, Debug)]
 pub struct InputInjectorModule {
     // Save the Mutator's BytesInput
     input: Vec<u8>,
     input_addr: GuestAddr,
+    // Where exit() / exit_group() of the target continues, 0 to abort() instead
+    exit_addr: GuestAddr,
     max_size: usize,
+    fault_injection: bool,
+    // Faults of the current input, see `FaultSchedule`
+    faults: FaultSchedule,
+    // Faultable syscalls seen in the current execution
+    faultable: usize,
 }
 
 impl InputInjectorModule {
@@ -26,6 +39,34 @@ impl InputInjectorModule {
     pub fn set_input_addr(&mut self, addr: GuestAddr) {
         self.input_addr = addr;
     }
+
+    pub fn set_exit_addr(&mut self, addr: GuestAddr) {
+        self.exit_addr = addr;
+    }
+
+    /// Split the fault schedule off the inputs, for syscall faults and allocation failures
+    pub fn set_fault_injection(&mut self, enabled: bool) {
+        self.fault_injection = enabled;
+    }
+
+    /// The fault schedule of the current input
+    pub fn faults(&self) -> &FaultSchedule {
+        &self.faults
+    }
+
+    /// Count a faultable syscall and return its fault, if it should fail
+    fn next_fault(&mut self) -> Option<u8> {
+        if !self.fault_injection {
+            return None;
+        }
+        let index = self.faultable;
+        self.faultable += 1;
+        let fault = self.faults.fault(index);
+        if fault.is_some() {
+            exec_event!("injector", "fault", index);
+        }
+        fault
+    }
 }
 
 impl<I, S> EmulatorModule<I, S> for InputInjectorModule
@@ -66,18 +107,20 @@ where
     ) where
         ET: EmulatorModuleTuple<I, S>,
     {   
-        log::debug!("InputInjectorModule::pre_exec running ...");
 
-        let mut tb = _input.target_bytes();
-        if tb.len() > self.max_size {
-            if let None = tb.truncate(self.max_size) {
-                log::error!("Failed to truncate input");
-                return;
-            }
-        }
+        let tb = _input.target_bytes();
+        // The fault schedule is never part of the data the target sees
+        let (data, faults) = if self.fault_injection {
+            FaultSchedule::split(&tb)
+        } else {
+            (&tb[..], FaultSchedule::default())
+        };
+        self.faults = faults;
+        self.faultable = 0;
 
         self.input.clear();
-        self.input.extend_from_slice(&tb);
+        self.input.extend_from_slice(&data[..data.len().min(self.max_size)]);
+        exec_event!("injector", "input", self.input.len());
 
         // clean and fill the input_addr for further mmap usage
         let written_buf = if self.input.len() > self.max_size {
@@ -88,6 +131,24 @@ where
         _qemu.write_mem(self.input_addr, written_buf).unwrap();
     }
 
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if self.fault_injection {
+            let counts = _state.metadata_or_insert_with(FaultCountsMeta::default);
+            counts.syscalls = counts.syscalls.max(self.faultable);
+        }
+    }
+
     fn address_filter(&self) -> &Self::ModuleAddressFilter {
         &NopAddressFilter
     }
@@ -123,16 +184,27 @@ where
     let sys_num = sys_num as i64;
     // Hook syscall read
     if sys_num == SYS_read {
-        log::debug!("Read syscall intercepted ...");
+        log::trace!(target: "injector", "read({a0}, {a1:#x}, {_a2:#x})");
         let input_injector_module = emulator_modules
             .get_mut::<InputInjectorModule>()
             .expect("Failed to get InputInjectorModule");
         
+        let mut requested = _a2 as usize;
+        if let Some(fault) = input_injector_module.next_fault() {
+            match FaultSchedule::read_fault(fault) {
+                ReadFault::Short(len) => requested = requested.min(len),
+                ReadFault::Errno(errno) => {
+                    log::trace!(target: "injector", "read fails with errno {errno}");
+                    return SyscallHookResult::new(Some(FaultSchedule::error(errno)));
+                }
+            }
+        }
+
         let input_len = input_injector_module.input.len();
-        let offset: usize = if _a2 == 0 {
+        let offset: usize = if requested == 0 {
             0
-        } else if _a2 as usize <= input_len {
-            _a2.try_into().unwrap()
+        } else if requested <= input_len {
+            requested
         } else {
             input_len
         };
@@ -144,46 +216,79 @@ where
 
         _qemu.write_mem(a1, drained.as_slice()).unwrap();
 
+        exec_event!("injector", "read", drained.len());
         // Return the number of bytes read
         SyscallHookResult::new(Some(drained.len() as u64))
     }
     else if sys_num == SYS_mmap {
         if _a2 == 1 && _a3 == 1 {
-            log::debug!("Mmap syscall intercepted ...");
             let input_injector_module = emulator_modules
                 .get_mut::<InputInjectorModule>()
                 .expect("Failed to get InputInjectorModule");
-            log::debug!("Mmap return address: {:#x}", input_injector_module.input_addr);
+            exec_event!("injector", "mmap", input_injector_module.input_addr);
+            log::trace!(target: "injector", "mmap -> {:#x}", input_injector_module.input_addr);
             SyscallHookResult::new(Some(input_injector_module.input_addr))
+        } else if _a4 as i32 == -1 {
+            // Anonymous mappings, e.g. of large allocations
+            let input_injector_module = emulator_modules
+                .get_mut::<InputInjectorModule>()
+                .expect("Failed to get InputInjectorModule");
+            match input_injector_module.next_fault() {
+                Some(_) => SyscallHookResult::new(Some(FaultSchedule::error(libc::ENOMEM))),
+                None => SyscallHookResult::new(None),
+            }
         } else {
             SyscallHookResult::new(None)
         }
     }
+    else if sys_num == SYS_openat {
+        let input_injector_module = emulator_modules
+            .get_mut::<InputInjectorModule>()
+            .expect("Failed to get InputInjectorModule");
+        match input_injector_module.next_fault() {
+            Some(fault) => {
+                let errno = FaultSchedule::open_errno(fault);
+                SyscallHookResult::new(Some(FaultSchedule::error(errno)))
+            }
+            None => SyscallHookResult::new(None),
+        }
+    }
     else if sys_num == SYS_munmap {
         let input_injector_module = emulator_modules
                 .get_mut::<InputInjectorModule>()
                 .expect("Failed to get InputInjectorModule");
         let addr = input_injector_module.input_addr;
-        log::debug!("Munmap args: {:#x}, {:#x}", a0, a1);
         if a0 == addr {
-            log::debug!("Munmap syscall intercepted ...");
+            log::trace!(target: "injector", "munmap({a0:#x}, {a1:#x})");
             SyscallHookResult::new(Some(0))
         } else {
             SyscallHookResult::new(None)
         }
     }
     else if sys_num == SYS_exit || sys_num == SYS_exit_group {
-        log::debug!("Exit / Exit group syscall intercepted ...");
+        exec_event!("injector", "exit", a0);
+        log::trace!(target: "injector", "exit({a0})");
         
-        // Simply abort() will cause the fuzzer treat it as a crash, so we need to set a flag to ignore it
+        // The run should neither be a crash nor a new corpus entry, so we need to set a flag to ignore it
         let state = _state.expect("No state found");
         let exec_meta = state
             .metadata_map_mut()
             .get_mut::<ExecMeta>()
             .expect("Can't get exec_meta");
         exec_meta.ignore = true;
-        
-        abort();
+
+        let exit_addr = emulator_modules
+            .get_mut::<InputInjectorModule>()
+            .expect("Failed to get InputInjectorModule")
+            .exit_addr;
+        if exit_addr == 0 {
+            // Simply abort() will cause the fuzzer treat it as a crash and restart the client
+            abort();
+        }
+
+        // Skip the syscall and continue at the end breakpoint, the harness returns from there
+        _qemu.write_reg(Regs::Pc, exit_addr).unwrap();
+        SyscallHookResult::new(Some(0))
     }
     else {
         SyscallHookResult::new(None)
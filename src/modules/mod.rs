@@ -1,8 +1,25 @@
+pub mod alloc_fail;
+pub mod checksum_patch;
+pub mod cmp_coverage;
+pub mod crash_info;
+pub mod exec_trace;
+pub mod fault_schedule;
+pub mod hang_pc;
 pub mod input_injector;
 pub mod register;
+pub mod sandbox;
+pub mod syscall_trace;
 
+pub use alloc_fail::AllocFailureModule;
+pub use checksum_patch::ChecksumPatchModule;
+pub use cmp_coverage::CmpCoverageModule;
+pub use crash_info::CrashInfoModule;
+pub use hang_pc::HangPcModule;
+pub use exec_trace::ExecTraceModule;
 pub use input_injector::InputInjectorModule;
 pub use register::RegisterResetModule;
+pub use sandbox::SandboxModule;
+pub use syscall_trace::SyscallTraceModule;
 use serde::{Deserialize, Serialize};
 // use std::cell::UnsafeCell;
 // use libafl_qemu::modules::NopAddressFilter;
@@ -66,7 +66,7 @@ where
     ) where
         ET: EmulatorModuleTuple<I, S>,
     {
-        log::debug!("RegisterResetModule::pre_exec running ...");
+        log::trace!(target: "register", "restoring registers");
         self.restore(_qemu);
     }
 
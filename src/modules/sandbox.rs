@@ -0,0 +1,337 @@
+//! A syscall policy for the fuzzed target.
+//!
+//! Syscalls are allowed, denied (failing with `errno`) or faked (returning 0 without being
+//! executed) by name, and files may only be opened for writing below the output directory
+//! or one of the `write_paths` of the policy. Syscalls the `InputInjectorModule` serves the
+//! input with are never blocked: every `read`, the shared read-only `mmap` of the input, and
+//! `munmap`, `exit` and `exit_group`. Other `mmap`s go through the policy.
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs,
+    os::unix::ffi::OsStringExt,
+    path::{Component, Path, PathBuf},
+};
+
+use libafl::Error;
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    sys_call_name, EmulatorModules, GuestAddr, Hook, Qemu, SyscallHookResult, SYS_exit,
+    SYS_exit_group, SYS_mmap, SYS_munmap, SYS_read,
+};
+use serde::Deserialize;
+
+use crate::trace::exec_event;
+
+/// Used by `--sandbox` without a policy file
+pub const DEFAULT_POLICY: &str = r#"
+deny = ["execve", "execveat", "kill", "tkill", "tgkill", "ptrace", "socket", "connect"]
+fake = ["unlinkat", "unlink", "rmdir", "renameat", "renameat2", "rename", "mkdirat", "mkdir",
+        "symlinkat", "linkat", "fchmodat", "fchownat", "truncate", "ftruncate"]
+write_paths = ["/dev/null", "/dev/stdout", "/dev/stderr", "/dev/tty"]
+"#;
+
+/// The highest syscall number we look names up for
+const MAX_SYSCALL: i64 = 1024;
+
+const O_ACCMODE: u64 = 0o3;
+const O_CREAT: u64 = 0o100;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+const AT_FDCWD: i64 = -100;
+const PROT_READ: GuestAddr = 0x1;
+const MAP_SHARED: GuestAddr = 0x1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+    Fake,
+}
+
+/// The policy file, see [`DEFAULT_POLICY`]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    /// For all syscalls that are not listed, `deny` turns `allow` into an allow list
+    #[serde(default)]
+    default: Action,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    fake: Vec<String>,
+    /// Returned (negated) by denied syscalls, `EPERM` by default
+    #[serde(default = "default_errno")]
+    errno: i32,
+    #[serde(default)]
+    write_paths: Vec<PathBuf>,
+}
+
+fn default_errno() -> i32 {
+    libc::EPERM
+}
+
+/// Where the flags of a syscall opening a file are
+#[derive(Debug, Clone, Copy)]
+enum OpenFlags {
+    /// `creat` always writes
+    None,
+    Arg(usize),
+    /// The first field of the `struct open_how` the argument points to, see `openat2(2)`
+    OpenHow(usize),
+}
+
+/// Where the path and the flags of the syscalls opening files are
+#[derive(Debug, Clone, Copy)]
+struct OpenArgs {
+    dirfd: Option<usize>,
+    path: usize,
+    flags: OpenFlags,
+}
+
+#[derive(Debug)]
+pub struct SandboxPolicy {
+    default: Action,
+    actions: HashMap<i64, Action>,
+    opens: HashMap<i64, OpenArgs>,
+    errno: i32,
+    write_paths: Vec<PathBuf>,
+}
+
+impl SandboxPolicy {
+    /// Load the policy from a TOML file, or the [`DEFAULT_POLICY`].
+    /// Writes below `output_dir` are always allowed.
+    pub fn load(path: Option<&Path>, output_dir: &Path) -> Result<Self, Error> {
+        let source = match path {
+            Some(path) => fs::read_to_string(path)?,
+            None => DEFAULT_POLICY.to_string(),
+        };
+        let file: PolicyFile = toml::from_str(&source)
+            .map_err(|e| Error::illegal_argument(format!("Invalid sandbox policy {path:?}: {e}")))?;
+
+        let numbers = (0..MAX_SYSCALL)
+            .filter_map(|nr| sys_call_name(nr as _).map(|name| (name, nr)))
+            .collect::<HashMap<&str, i64>>();
+
+        let mut actions = HashMap::new();
+        for (names, action) in [
+            (&file.allow, Action::Allow),
+            (&file.deny, Action::Deny),
+            (&file.fake, Action::Fake),
+        ] {
+            for name in names {
+                // The default policy lists syscalls that only exist on some architectures
+                if let Some(nr) = numbers.get(name.as_str()) {
+                    actions.insert(*nr, action);
+                } else if path.is_some() {
+                    return Err(Error::illegal_argument(format!(
+                        "Unknown syscall {name} in the sandbox policy"
+                    )));
+                }
+            }
+        }
+
+        let mut opens = HashMap::new();
+        for (name, args) in [
+            ("openat", OpenArgs { dirfd: Some(0), path: 1, flags: OpenFlags::Arg(2) }),
+            ("openat2", OpenArgs { dirfd: Some(0), path: 1, flags: OpenFlags::OpenHow(2) }),
+            ("open", OpenArgs { dirfd: None, path: 0, flags: OpenFlags::Arg(1) }),
+            ("creat", OpenArgs { dirfd: None, path: 0, flags: OpenFlags::None }),
+        ] {
+            if let Some(nr) = numbers.get(name) {
+                opens.insert(*nr, args);
+            }
+        }
+
+        let cwd = env::current_dir()?;
+        let mut write_paths = file
+            .write_paths
+            .iter()
+            .map(|path| normalize(&cwd.join(path)))
+            .collect::<Vec<PathBuf>>();
+        write_paths.push(normalize(&cwd.join(output_dir)));
+
+        Ok(Self {
+            default: file.default,
+            actions,
+            opens,
+            errno: file.errno,
+            write_paths,
+        })
+    }
+
+    fn action(&self, qemu: Qemu, nr: i64, args: &[GuestAddr; 4]) -> Action {
+        if [SYS_read, SYS_munmap, SYS_exit, SYS_exit_group].contains(&nr)
+            || (nr == SYS_mmap && args[2] == PROT_READ && args[3] == MAP_SHARED)
+        {
+            return Action::Allow;
+        }
+        if let Some(open) = self.opens.get(&nr) {
+            return self.check_open(qemu, *open, args);
+        }
+        self.actions.get(&nr).copied().unwrap_or(self.default)
+    }
+
+    fn check_open(&self, qemu: Qemu, open: OpenArgs, args: &[GuestAddr; 4]) -> Action {
+        let flags = match open.flags {
+            OpenFlags::None => None,
+            OpenFlags::Arg(idx) => Some(args[idx] as u64),
+            OpenFlags::OpenHow(idx) => {
+                let mut flags = [0_u8; 8];
+                if qemu.read_mem(args[idx], &mut flags).is_err() {
+                    return Action::Deny;
+                }
+                Some(u64::from_le_bytes(flags))
+            }
+        };
+        let writes = flags.map_or(true, |flags| {
+            flags & O_ACCMODE != 0 || flags & (O_CREAT | O_TRUNC | O_APPEND) != 0
+        });
+        if !writes {
+            return Action::Allow;
+        }
+
+        let Some(path) = read_path(qemu, args[open.path]) else {
+            return Action::Deny;
+        };
+        let path = if path.is_absolute() {
+            path
+        } else if open.dirfd.map_or(true, |idx| args[idx] as i64 == AT_FDCWD) {
+            match env::current_dir() {
+                Ok(cwd) => cwd.join(path),
+                Err(_) => return Action::Deny,
+            }
+        } else {
+            // Relative to a directory we can't resolve
+            return Action::Deny;
+        };
+
+        let path = normalize(&path);
+        if self.write_paths.iter().any(|allowed| path.starts_with(allowed)) {
+            Action::Allow
+        } else {
+            Action::Deny
+        }
+    }
+}
+
+fn read_path(qemu: Qemu, addr: GuestAddr) -> Option<PathBuf> {
+    let mut path = Vec::new();
+    let mut chunk = [0_u8; 8];
+    while path.len() < libc::PATH_MAX as usize {
+        qemu.read_mem(addr + path.len() as GuestAddr, &mut chunk).ok()?;
+        if let Some(end) = chunk.iter().position(|b| *b == 0) {
+            path.extend_from_slice(&chunk[..end]);
+            return Some(PathBuf::from(OsString::from_vec(path)));
+        }
+        path.extend_from_slice(&chunk);
+    }
+    None
+}
+
+/// Resolve `.` and `..` without touching the file system, the target may not see the same files
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[derive(Debug, Default)]
+pub struct SandboxModule {
+    policy: Option<SandboxPolicy>,
+}
+
+impl SandboxModule {
+    /// Without a policy the module doesn't install any hook
+    pub fn new(policy: Option<SandboxPolicy>) -> Self {
+        Self { policy }
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for SandboxModule
+where
+    S: Unpin,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if self.policy.is_none() {
+            return;
+        }
+        log::debug!("SandboxModule::first_exec running ...");
+
+        _emulator_modules.pre_syscalls(Hook::Function(sandbox_syscall::<ET, I, S>));
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+#[expect(clippy::too_many_arguments)]
+fn sandbox_syscall<ET, I, S>(
+    qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _state: Option<&mut S>,
+    sys_num: i32,
+    a0: GuestAddr,
+    a1: GuestAddr,
+    a2: GuestAddr,
+    a3: GuestAddr,
+    _a4: GuestAddr,
+    _a5: GuestAddr,
+    _a6: GuestAddr,
+    _a7: GuestAddr,
+) -> SyscallHookResult
+where
+    S: Unpin,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let Some(policy) = emulator_modules
+        .get_mut::<SandboxModule>()
+        .and_then(|module| module.policy.as_ref())
+    else {
+        return SyscallHookResult::new(None);
+    };
+
+    let nr = i64::from(sys_num);
+    match policy.action(qemu, nr, &[a0, a1, a2, a3]) {
+        Action::Allow => SyscallHookResult::new(None),
+        Action::Deny => {
+            exec_event!("sandbox", "deny", nr);
+            log::debug!(target: "sandbox", "denied syscall {nr}");
+            SyscallHookResult::new(Some((-i64::from(policy.errno)) as GuestAddr))
+        }
+        Action::Fake => {
+            exec_event!("sandbox", "fake", nr);
+            log::debug!(target: "sandbox", "faked syscall {nr}");
+            SyscallHookResult::new(Some(0))
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! An strace-like record of the syscalls of each execution.
+//!
+//! The raw records of the current execution are kept in the [`SyscallTraceMeta`] of the
+//! state for the `SyscallCoverageFeedback`. In replay mode, the decoded calls are also
+//! written to a log file, one line per syscall.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use libafl::{executors::ExitKind, observers::ObserversTuple, Error, HasMetadata};
+use libafl_qemu::{
+    modules::{utils::filters::NopAddressFilter, EmulatorModule, EmulatorModuleTuple},
+    sys_call_name, EmulatorModules, GuestAddr, Hook, Qemu, SYS_brk, SYS_close, SYS_connect,
+    SYS_execve, SYS_exit, SYS_exit_group, SYS_fstat, SYS_ioctl, SYS_kill, SYS_lseek, SYS_mmap,
+    SYS_mprotect, SYS_munmap, SYS_newfstatat, SYS_openat, SYS_pread64, SYS_pwrite64, SYS_read,
+    SYS_readv, SYS_renameat, SYS_socket, SYS_unlinkat, SYS_write, SYS_writev,
+};
+use serde::{Deserialize, Serialize};
+
+/// Syscalls recorded per execution, a runaway loop shouldn't eat the memory
+const MAX_RECORDS: usize = 4096;
+
+/// Longest string argument read from the guest
+const MAX_STR_LEN: usize = 64;
+
+const AT_FDCWD: i64 = -100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Fd,
+    Int,
+    Len,
+    Flags,
+    Ptr,
+    Str,
+}
+
+/// The argument kinds of the syscalls we decode, all others are printed as 6 raw values
+fn signature(nr: i64) -> Option<&'static [Arg]> {
+    use Arg::{Fd, Flags, Int, Len, Ptr, Str};
+
+    let args: &'static [Arg] = match nr {
+        n if n == SYS_read || n == SYS_write => &[Fd, Ptr, Len],
+        n if n == SYS_pread64 || n == SYS_pwrite64 => &[Fd, Ptr, Len, Int],
+        n if n == SYS_readv || n == SYS_writev => &[Fd, Ptr, Int],
+        n if n == SYS_openat => &[Fd, Str, Flags, Flags],
+        n if n == SYS_close => &[Fd],
+        n if n == SYS_lseek => &[Fd, Int, Int],
+        n if n == SYS_fstat => &[Fd, Ptr],
+        n if n == SYS_newfstatat => &[Fd, Str, Ptr, Flags],
+        n if n == SYS_ioctl => &[Fd, Flags, Ptr],
+        n if n == SYS_mmap => &[Ptr, Len, Flags, Flags, Fd, Int],
+        n if n == SYS_munmap => &[Ptr, Len],
+        n if n == SYS_mprotect => &[Ptr, Len, Flags],
+        n if n == SYS_brk => &[Ptr],
+        n if n == SYS_unlinkat => &[Fd, Str, Flags],
+        n if n == SYS_renameat => &[Fd, Str, Fd, Str],
+        n if n == SYS_execve => &[Str, Ptr, Ptr],
+        n if n == SYS_socket => &[Int, Int, Int],
+        n if n == SYS_connect => &[Fd, Ptr, Len],
+        n if n == SYS_kill => &[Int, Int],
+        n if n == SYS_exit || n == SYS_exit_group => &[Int],
+        _ => return None,
+    };
+    Some(args)
+}
+
+/// Values close to each other fall into the same bucket, like AFL's hitcounts
+fn bucket(value: u64) -> u64 {
+    u64::from(u64::BITS - value.leading_zeros())
+}
+
+/// The errno of a failed syscall
+fn errno(ret: GuestAddr) -> Option<i64> {
+    let ret = ret as i64;
+    (-4095..0).contains(&ret).then_some(-ret)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SyscallRecord {
+    pub nr: i32,
+    pub args: [GuestAddr; 6],
+    pub ret: GuestAddr,
+}
+
+/// What the `SyscallCoverageFeedback` considers a distinct behavior of a syscall
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SyscallKey {
+    nr: i32,
+    args: [u64; 6],
+    ret: i64,
+}
+
+impl SyscallRecord {
+    /// The syscall with its file descriptors and flags, sizes bucketed and pointers dropped
+    pub fn key(&self) -> SyscallKey {
+        let mut args = [0; 6];
+        if let Some(kinds) = signature(i64::from(self.nr)) {
+            for (idx, kind) in kinds.iter().enumerate() {
+                let value = self.args[idx] as u64;
+                args[idx] = match kind {
+                    Arg::Fd | Arg::Flags => value,
+                    Arg::Int | Arg::Len => bucket(value),
+                    Arg::Ptr | Arg::Str => 0,
+                };
+            }
+        }
+        let ret = match errno(self.ret) {
+            Some(errno) => -errno,
+            None => bucket(self.ret as u64) as i64,
+        };
+        SyscallKey {
+            nr: self.nr,
+            args,
+            ret,
+        }
+    }
+
+    fn decode(&self, qemu: Qemu) -> String {
+        let nr = i64::from(self.nr);
+        let name = sys_call_name(nr as _).map_or_else(|| format!("syscall_{nr}"), str::to_string);
+        let args = match signature(nr) {
+            Some(kinds) => kinds
+                .iter()
+                .zip(self.args)
+                .map(|(kind, value)| decode_arg(qemu, *kind, value))
+                .collect::<Vec<String>>(),
+            None => self.args.iter().map(|value| format!("{value:#x}")).collect(),
+        };
+        let ret = match errno(self.ret) {
+            Some(errno) => format!("-1 (errno {errno})"),
+            None => format!("{:#x}", self.ret),
+        };
+        format!("{name}({}) = {ret}", args.join(", "))
+    }
+}
+
+fn decode_arg(qemu: Qemu, kind: Arg, value: GuestAddr) -> String {
+    match kind {
+        Arg::Fd if value as i64 == AT_FDCWD => "AT_FDCWD".to_string(),
+        Arg::Fd | Arg::Int => format!("{}", value as i64),
+        Arg::Len => format!("{value}"),
+        Arg::Flags | Arg::Ptr => format!("{value:#x}"),
+        Arg::Str if value == 0 => "NULL".to_string(),
+        Arg::Str => {
+            let mut buf = [0_u8; MAX_STR_LEN];
+            // The string may end right before an unmapped page, so read it in small chunks
+            let mut len = 0;
+            while len < MAX_STR_LEN
+                && qemu
+                    .read_mem(value + len as GuestAddr, &mut buf[len..len + 8])
+                    .is_ok()
+            {
+                len += 8;
+                if buf[len - 8..len].contains(&0) {
+                    break;
+                }
+            }
+            let end = buf[..len].iter().position(|b| *b == 0);
+            let truncated = if end.is_some() { "" } else { "..." };
+            format!(
+                "{:?}{truncated}",
+                String::from_utf8_lossy(&buf[..end.unwrap_or(len)])
+            )
+        }
+    }
+}
+
+/// The syscalls of the current execution
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyscallTraceMeta {
+    pub records: Vec<SyscallRecord>,
+}
+
+libafl_bolts::impl_serdeany!(SyscallTraceMeta);
+
+#[derive(Debug)]
+pub struct SyscallTraceModule {
+    enabled: bool,
+    log: Option<BufWriter<File>>,
+}
+
+impl SyscallTraceModule {
+    /// A disabled module doesn't install any hook
+    pub fn new(enabled: bool, log: Option<&Path>) -> Result<Self, Error> {
+        let log = log
+            .map(|path| {
+                File::create(path).map(BufWriter::new).map_err(|e| {
+                    Error::illegal_argument(format!("Failed to create syscall log {path:?}: {e}"))
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            enabled: enabled || log.is_some(),
+            log,
+        })
+    }
+
+    fn flush(&mut self) {
+        if let Some(log) = &mut self.log {
+            if let Err(e) = log.flush() {
+                log::error!("Failed to flush the syscall log: {e}");
+            }
+        }
+    }
+}
+
+impl<I, S> EmulatorModule<I, S> for SyscallTraceModule
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+{
+    type ModuleAddressFilter = NopAddressFilter;
+
+    /// Before the crash hook of the executor, see `CrashInfoModule::post_qemu_init`
+    fn post_qemu_init<ET>(&mut self, _qemu: Qemu, _emulator_modules: &mut EmulatorModules<ET, I, S>)
+    where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if self.enabled {
+            _emulator_modules.crash_function(flush_on_crash::<ET, I, S>);
+        }
+    }
+
+    fn first_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if !self.enabled {
+            return;
+        }
+        log::debug!("SyscallTraceModule::first_exec running ...");
+
+        _emulator_modules.post_syscalls(Hook::Function(on_syscall::<ET, I, S>));
+        _state.add_metadata(SyscallTraceMeta::default());
+    }
+
+    fn pre_exec<ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+    ) where
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        if let Ok(meta) = _state.metadata_mut::<SyscallTraceMeta>() {
+            meta.records.clear();
+        }
+    }
+
+    fn post_exec<OT, ET>(
+        &mut self,
+        _qemu: Qemu,
+        _emulator_modules: &mut EmulatorModules<ET, I, S>,
+        _state: &mut S,
+        _input: &I,
+        _observers: &mut OT,
+        _exit_kind: &mut ExitKind,
+    ) where
+        OT: ObserversTuple<I, S>,
+        ET: EmulatorModuleTuple<I, S>,
+    {
+        self.flush();
+    }
+
+    fn address_filter(&self) -> &Self::ModuleAddressFilter {
+        &NopAddressFilter
+    }
+
+    fn address_filter_mut(&mut self) -> &mut Self::ModuleAddressFilter {
+        unimplemented!("This should never be called")
+    }
+}
+
+/// Runs after the `InputInjectorModule` hooks, so injected results are recorded as well
+#[expect(clippy::too_many_arguments)]
+fn on_syscall<ET, I, S>(
+    qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    state: Option<&mut S>,
+    result: GuestAddr,
+    sys_num: i32,
+    a0: GuestAddr,
+    a1: GuestAddr,
+    a2: GuestAddr,
+    a3: GuestAddr,
+    a4: GuestAddr,
+    a5: GuestAddr,
+    _a6: GuestAddr,
+    _a7: GuestAddr,
+) -> GuestAddr
+where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    let record = SyscallRecord {
+        nr: sys_num,
+        args: [a0, a1, a2, a3, a4, a5],
+        ret: result,
+    };
+
+    let module = emulator_modules
+        .get_mut::<SyscallTraceModule>()
+        .expect("Failed to get SyscallTraceModule");
+    if let Some(log) = &mut module.log {
+        let _ = writeln!(log, "{}", record.decode(qemu));
+    }
+
+    if let Some(meta) = state.and_then(|state| state.metadata_mut::<SyscallTraceMeta>().ok()) {
+        if meta.records.len() < MAX_RECORDS {
+            meta.records.push(record);
+        }
+    }
+    result
+}
+
+fn flush_on_crash<ET, I, S>(
+    _qemu: Qemu,
+    emulator_modules: &mut EmulatorModules<ET, I, S>,
+    _target_signal: i32,
+) where
+    S: Unpin + HasMetadata,
+    I: Unpin,
+    ET: EmulatorModuleTuple<I, S>,
+{
+    if let Some(module) = emulator_modules.get_mut::<SyscallTraceModule>() {
+        module.flush();
+    }
+}
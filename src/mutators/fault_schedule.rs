@@ -0,0 +1,77 @@
+use std::{borrow::Cow, num::NonZeroUsize};
+
+use libafl::{
+    inputs::{BytesInput, HasTargetBytes},
+    mutators::{MutationResult, Mutator},
+    state::HasRand,
+    Error, HasMetadata,
+};
+use libafl_bolts::{nonzero, rands::Rand, Named};
+
+use crate::modules::fault_schedule::{
+    FaultCountsMeta, FaultSchedule, ALLOC_FLAG, MAX_FAULTS, MAX_INDEX,
+};
+
+/// Adds, changes or removes an entry of the fault schedule at the end of the input,
+/// without touching the data the target sees. Indices are picked below the most syscalls or
+/// allocations an execution made so far, see [`FaultCountsMeta`].
+#[derive(Debug, Default)]
+pub struct FaultScheduleMutator {
+    syscalls: bool,
+    allocs: bool,
+}
+
+impl FaultScheduleMutator {
+    /// Schedule syscall faults, allocation failures or both
+    pub fn new(syscalls: bool, allocs: bool) -> Self {
+        Self { syscalls, allocs }
+    }
+}
+
+impl<S> Mutator<BytesInput, S> for FaultScheduleMutator
+where
+    S: HasRand + HasMetadata,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        let bytes = input.target_bytes();
+        let (data, mut schedule) = FaultSchedule::split(&bytes);
+        let (syscalls, allocs) = state
+            .metadata::<FaultCountsMeta>()
+            .map_or((0, 0), |counts| (counts.syscalls, counts.allocs));
+
+        let rand = state.rand_mut();
+        let alloc = self.allocs && (!self.syscalls || rand.coinflip(0.5));
+        let seen = if alloc { allocs } else { syscalls };
+        let range = NonZeroUsize::new(seen.min(MAX_INDEX as usize + 1)).unwrap_or(nonzero!(1));
+        let mut index = rand.below(range) as u16;
+        if alloc {
+            index |= ALLOC_FLAG;
+        }
+        let fault = rand.below(nonzero!(256)) as u8;
+        let entries = schedule.entries_mut();
+        if entries.len() < MAX_FAULTS && (entries.is_empty() || rand.coinflip(0.5)) {
+            entries.push((index, fault));
+        } else {
+            let idx = rand.below(NonZeroUsize::new(entries.len()).unwrap());
+            if rand.coinflip(0.25) {
+                entries.swap_remove(idx);
+            } else if rand.coinflip(0.5) {
+                entries[idx].0 = index;
+            } else {
+                entries[idx].1 = fault;
+            }
+        }
+
+        let mutated = schedule.join(data);
+        drop(bytes);
+        *input = BytesInput::new(mutated);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+impl Named for FaultScheduleMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        static NAME: Cow<'static, str> = Cow::Borrowed("FaultScheduleMutator");
+        &NAME
+    }
+}
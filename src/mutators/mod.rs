@@ -0,0 +1,7 @@
+pub mod fault_schedule;
+pub mod strategy;
+pub mod tiff;
+
+pub use fault_schedule::FaultScheduleMutator;
+pub use strategy::{MOptParams, MutatorStrategy, StrategyMutator};
+pub use tiff::tiff_mutations;
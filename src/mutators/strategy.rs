@@ -0,0 +1,124 @@
+//! The main mutator of a core, chosen with `--mutator` and `--cmplog-mutator`.
+use std::{borrow::Cow, fmt, rc::Rc};
+
+use clap::ValueEnum;
+use libafl::{
+    corpus::CorpusId,
+    inputs::BytesInput,
+    mutators::{
+        havoc_crossover, havoc_mutations, tokens_mutations, MutationResult, Mutator,
+        SpliceMutator, StdMOptMutator, StdScheduledMutator,
+    },
+    Error,
+};
+use libafl_bolts::{
+    tuples::{tuple_list, Merge},
+    Named,
+};
+
+use crate::{
+    format::{format_mutations, FormatSpec},
+    instance::ClientState,
+    mutators::tiff_mutations,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MutatorStrategy {
+    /// Havoc, with token insertion and replacement
+    Havoc,
+    /// The havoc and token mutations, scheduled by MOpt
+    Mopt,
+    /// Only splicing and crossover with other corpus entries
+    Splice,
+    /// Havoc, with the token mutations picked four times as often
+    Tokens,
+    /// The mutations of `--format`, or the TIFF ones of `--tiff`
+    Structure,
+}
+
+/// The parameters of MOpt, see `StdMOptMutator::new`
+#[derive(Clone, Copy, Debug)]
+pub struct MOptParams {
+    pub max_stack_pow: usize,
+    pub swarms: usize,
+}
+
+/// The mutator of a strategy, behind a single type so the stages don't depend on the choice
+pub struct StrategyMutator {
+    strategy: MutatorStrategy,
+    inner: Box<dyn Mutator<BytesInput, ClientState>>,
+}
+
+impl StrategyMutator {
+    pub fn new(
+        state: &mut ClientState,
+        strategy: MutatorStrategy,
+        mopt: MOptParams,
+        format_spec: Option<&Rc<FormatSpec>>,
+    ) -> Result<Self, Error> {
+        let inner: Box<dyn Mutator<BytesInput, ClientState>> = match strategy {
+            MutatorStrategy::Havoc => Box::new(StdScheduledMutator::new(
+                havoc_mutations().merge(tokens_mutations()),
+            )),
+            MutatorStrategy::Mopt => Box::new(StdMOptMutator::new(
+                state,
+                havoc_mutations().merge(tokens_mutations()),
+                mopt.max_stack_pow,
+                mopt.swarms,
+            )?),
+            MutatorStrategy::Splice => Box::new(StdScheduledMutator::new(
+                tuple_list!(SpliceMutator::new()).merge(havoc_crossover()),
+            )),
+            MutatorStrategy::Tokens => Box::new(StdScheduledMutator::new(
+                havoc_mutations()
+                    .merge(tokens_mutations())
+                    .merge(tokens_mutations())
+                    .merge(tokens_mutations())
+                    .merge(tokens_mutations()),
+            )),
+            MutatorStrategy::Structure => match format_spec {
+                Some(spec) => Box::new(StdScheduledMutator::new(format_mutations(
+                    spec,
+                    StdScheduledMutator::new(havoc_mutations()),
+                ))),
+                // `--tiff`, see `FuzzerOptions::validate`
+                None => Box::new(StdScheduledMutator::new(tiff_mutations())),
+            },
+        };
+        log::info!("Mutator strategy: {strategy:?}");
+        Ok(Self { strategy, inner })
+    }
+}
+
+impl fmt::Debug for StrategyMutator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StrategyMutator")
+            .field("strategy", &self.strategy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mutator<BytesInput, ClientState> for StrategyMutator {
+    fn mutate(
+        &mut self,
+        state: &mut ClientState,
+        input: &mut BytesInput,
+    ) -> Result<MutationResult, Error> {
+        self.inner.mutate(state, input)
+    }
+
+    /// MOpt learns from the inputs its mutations found
+    fn post_exec(
+        &mut self,
+        state: &mut ClientState,
+        new_corpus_id: Option<CorpusId>,
+    ) -> Result<(), Error> {
+        self.inner.post_exec(state, new_corpus_id)
+    }
+}
+
+impl Named for StrategyMutator {
+    fn name(&self) -> &Cow<'static, str> {
+        self.inner.name()
+    }
+}
@@ -0,0 +1,813 @@
+//! Structure-aware mutations of TIFF inputs.
+//!
+//! An input is parsed into its IFDs, their entries and the strip / tile data they point to,
+//! mutated on that level and written back with a fresh layout: entry data, strips and the
+//! IFD chain are placed one after the other and all offsets, as well as the strip / tile
+//! byte counts, are recomputed. Offsets of other IFD pointers (EXIF, SubIFDs) are kept as
+//! plain values. Inputs that don't parse as TIFF are skipped, the havoc stage still covers
+//! them. A fault schedule at the end of the input is kept as it is.
+use std::{borrow::Cow, collections::HashSet, num::NonZeroUsize};
+
+use libafl::{
+    inputs::{BytesInput, HasTargetBytes},
+    mutators::{MutationResult, Mutator},
+    state::{HasMaxSize, HasRand},
+    Error,
+};
+use libafl_bolts::{
+    nonzero,
+    rands::Rand,
+    tuples::{tuple_list, tuple_list_type},
+    Named,
+};
+
+use crate::modules::fault_schedule::FaultSchedule;
+
+/// Bounds for parsing, a crafted file shouldn't make us loop or allocate forever
+const MAX_IFDS: usize = 16;
+const MAX_ENTRIES: usize = 512;
+const MAX_STRIPS: usize = 4096;
+
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+
+/// Tags libtiff looks at while decoding, with their usual type
+const KNOWN_TAGS: [(u16, u16); 28] = [
+    (254, TYPE_LONG),   // NewSubfileType
+    (256, TYPE_LONG),   // ImageWidth
+    (257, TYPE_LONG),   // ImageLength
+    (258, TYPE_SHORT),  // BitsPerSample
+    (259, TYPE_SHORT),  // Compression
+    (262, TYPE_SHORT),  // PhotometricInterpretation
+    (266, TYPE_SHORT),  // FillOrder
+    (274, TYPE_SHORT),  // Orientation
+    (277, TYPE_SHORT),  // SamplesPerPixel
+    (278, TYPE_LONG),   // RowsPerStrip
+    (284, TYPE_SHORT),  // PlanarConfiguration
+    (292, TYPE_LONG),   // T4Options
+    (293, TYPE_LONG),   // T6Options
+    (296, TYPE_SHORT),  // ResolutionUnit
+    (317, TYPE_SHORT),  // Predictor
+    (320, TYPE_SHORT),  // ColorMap
+    (322, TYPE_LONG),   // TileWidth
+    (323, TYPE_LONG),   // TileLength
+    (330, TYPE_LONG),   // SubIFDs
+    (332, TYPE_SHORT),  // InkSet
+    (338, TYPE_SHORT),  // ExtraSamples
+    (339, TYPE_SHORT),  // SampleFormat
+    (347, 7),           // JPEGTables
+    (530, TYPE_SHORT),  // YCbCrSubSampling
+    (532, 5),           // ReferenceBlackWhite
+    (32997, TYPE_LONG), // ImageDepth
+    (34665, TYPE_LONG), // ExifIFD
+    (34675, 7),         // ICCProfile
+];
+
+const INTERESTING: [u64; 12] = [
+    0,
+    1,
+    2,
+    7,
+    8,
+    16,
+    0x7f,
+    0xff,
+    0x7fff,
+    0xffff,
+    0x7fff_ffff,
+    u64::MAX,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn read(self, bytes: &[u8]) -> u64 {
+        let mut buf = [0_u8; 8];
+        match self {
+            Self::Little => {
+                buf[..bytes.len()].copy_from_slice(bytes);
+                u64::from_le_bytes(buf)
+            }
+            Self::Big => {
+                buf[8 - bytes.len()..].copy_from_slice(bytes);
+                u64::from_be_bytes(buf)
+            }
+        }
+    }
+
+    fn write(self, bytes: &mut [u8], value: u64) {
+        let len = bytes.len();
+        match self {
+            Self::Little => bytes.copy_from_slice(&value.to_le_bytes()[..len]),
+            Self::Big => bytes.copy_from_slice(&value.to_be_bytes()[8 - len..]),
+        }
+    }
+
+    fn u16(self, bytes: &[u8], offset: usize) -> Option<u16> {
+        bytes.get(offset..offset + 2).map(|b| self.read(b) as u16)
+    }
+
+    fn u32(self, bytes: &[u8], offset: usize) -> Option<u32> {
+        bytes.get(offset..offset + 4).map(|b| self.read(b) as u32)
+    }
+}
+
+/// Size of one value of a field type, unknown types are handled as bytes
+fn type_size(typ: u16) -> usize {
+    match typ {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+/// Size of the numbers a value is made of, rationals are two `u32`s
+fn element_size(typ: u16) -> usize {
+    match typ {
+        5 | 10 => 4,
+        typ => type_size(typ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub tag: u16,
+    pub typ: u16,
+    pub count: u32,
+    /// `count` values, in the byte order of the file
+    pub data: Vec<u8>,
+}
+
+impl Entry {
+    fn new(tag: u16, typ: u16, count: u32) -> Self {
+        Self {
+            tag,
+            typ,
+            count,
+            data: vec![0; count as usize * type_size(typ)],
+        }
+    }
+
+    fn elements(&self) -> usize {
+        self.data.len() / element_size(self.typ)
+    }
+
+    fn element(&self, order: ByteOrder, idx: usize) -> u64 {
+        let size = element_size(self.typ);
+        order.read(&self.data[idx * size..(idx + 1) * size])
+    }
+
+    fn set_element(&mut self, order: ByteOrder, idx: usize, value: u64) {
+        let size = element_size(self.typ);
+        order.write(&mut self.data[idx * size..(idx + 1) * size], value);
+    }
+
+    fn values(&self, order: ByteOrder) -> Vec<u64> {
+        (0..self.elements()).map(|idx| self.element(order, idx)).collect()
+    }
+
+    /// Change the type or the count, keeping as much of the old data as fits
+    fn resize(&mut self, typ: u16, count: u32) {
+        self.typ = typ;
+        self.count = count;
+        self.data.resize(count as usize * type_size(typ), 0);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ifd {
+    pub entries: Vec<Entry>,
+    /// The strips or tiles of the image, in the order of the offsets
+    pub strips: Vec<Vec<u8>>,
+}
+
+impl Ifd {
+    fn entry(&self, tag: u16) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+
+    /// The offset and byte count tags of the image data, tiles win over strips
+    fn data_tags(&self) -> Option<(u16, u16)> {
+        [
+            (TAG_TILE_OFFSETS, TAG_TILE_BYTE_COUNTS),
+            (TAG_STRIP_OFFSETS, TAG_STRIP_BYTE_COUNTS),
+        ]
+        .into_iter()
+        .find(|(offsets, _)| self.entry(*offsets).is_some())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tiff {
+    pub order: ByteOrder,
+    pub ifds: Vec<Ifd>,
+}
+
+impl Tiff {
+    /// Parse a classic (not Big-) TIFF file. Truncated data is cut to what the file holds.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let order = match bytes.get(..4)? {
+            b"II*\0" => ByteOrder::Little,
+            b"MM\0*" => ByteOrder::Big,
+            _ => return None,
+        };
+
+        let mut ifds = Vec::new();
+        let mut visited = HashSet::new();
+        let mut offset = order.u32(bytes, 4)? as usize;
+        while offset != 0 && ifds.len() < MAX_IFDS && visited.insert(offset) {
+            let Some(count) = order.u16(bytes, offset) else {
+                break;
+            };
+            let count = count as usize;
+            let mut ifd = Ifd::default();
+            for idx in 0..count.min(MAX_ENTRIES) {
+                let pos = offset + 2 + idx * 12;
+                let Some(raw) = bytes.get(pos..pos + 12) else {
+                    break;
+                };
+                ifd.entries.push(parse_entry(bytes, order, raw));
+            }
+            ifd.strips = parse_strips(bytes, order, &ifd);
+            ifds.push(ifd);
+
+            offset = order
+                .u32(bytes, offset + 2 + count * 12)
+                .unwrap_or_default() as usize;
+        }
+
+        (!ifds.is_empty()).then_some(Self { order, ifds })
+    }
+
+    /// Lay the file out again: each IFD is followed by the data of its entries and its strips
+    pub fn serialize(&self) -> Vec<u8> {
+        let order = self.order;
+        let mut out = match order {
+            ByteOrder::Little => b"II*\0".to_vec(),
+            ByteOrder::Big => b"MM\0*".to_vec(),
+        };
+        // Where the offset of the next IFD goes
+        let mut next_ptr = out.len();
+        out.extend_from_slice(&[0; 4]);
+
+        for ifd in &self.ifds {
+            let entries = layout_entries(ifd, order);
+
+            align(&mut out);
+            let table = out.len();
+            let mut buf = [0_u8; 4];
+            order.write(&mut buf, table as u64);
+            out[next_ptr..next_ptr + 4].copy_from_slice(&buf);
+
+            out.resize(table + 2 + entries.len() * 12 + 4, 0);
+            order.write(&mut out[table..table + 2], entries.len() as u64);
+            next_ptr = table + 2 + entries.len() * 12;
+
+            // Strips go after the entry data, their offsets are patched in below
+            let mut strip_offsets = Vec::new();
+            let mut offsets_data = None;
+            for (idx, entry) in entries.iter().enumerate() {
+                let pos = table + 2 + idx * 12;
+                order.write(&mut out[pos..pos + 2], u64::from(entry.tag));
+                order.write(&mut out[pos + 2..pos + 4], u64::from(entry.typ));
+                order.write(&mut out[pos + 4..pos + 8], u64::from(entry.count));
+
+                let data_pos = if entry.data.len() <= 4 {
+                    out[pos + 8..pos + 8 + entry.data.len()].copy_from_slice(&entry.data);
+                    pos + 8
+                } else {
+                    align(&mut out);
+                    let data_pos = out.len();
+                    order.write(&mut out[pos + 8..pos + 12], data_pos as u64);
+                    out.extend_from_slice(&entry.data);
+                    data_pos
+                };
+                if Some(entry.tag) == ifd.data_tags().map(|(offsets, _)| offsets) {
+                    offsets_data = Some(data_pos);
+                }
+            }
+            for strip in &ifd.strips {
+                align(&mut out);
+                strip_offsets.push(out.len() as u64);
+                out.extend_from_slice(strip);
+            }
+            if let Some(data_pos) = offsets_data {
+                for (idx, offset) in strip_offsets.into_iter().enumerate() {
+                    let pos = data_pos + idx * 4;
+                    order.write(&mut out[pos..pos + 4], offset);
+                }
+            }
+        }
+        out
+    }
+
+    /// Swap the byte order, converting all values
+    fn swap_byte_order(&mut self) {
+        let from = self.order;
+        let to = match from {
+            ByteOrder::Little => ByteOrder::Big,
+            ByteOrder::Big => ByteOrder::Little,
+        };
+        for entry in self.ifds.iter_mut().flat_map(|ifd| ifd.entries.iter_mut()) {
+            let values = entry.values(from);
+            for (idx, value) in values.into_iter().enumerate() {
+                entry.set_element(to, idx, value);
+            }
+        }
+        self.order = to;
+    }
+}
+
+fn parse_entry(bytes: &[u8], order: ByteOrder, raw: &[u8]) -> Entry {
+    let tag = order.read(&raw[..2]) as u16;
+    let typ = order.read(&raw[2..4]) as u16;
+    let count = order.read(&raw[4..8]) as u32;
+
+    let size = type_size(typ);
+    let len = (count as usize).saturating_mul(size);
+    let data = if len <= 4 {
+        raw[8..8 + len].to_vec()
+    } else {
+        let offset = order.read(&raw[8..12]) as usize;
+        let available = bytes.len().saturating_sub(offset);
+        bytes[offset.min(bytes.len())..][..len.min(available)].to_vec()
+    };
+
+    // Keep `count` in line with the data we actually got
+    let count = (data.len() / size) as u32;
+    let mut data = data;
+    data.truncate(count as usize * size);
+    Entry {
+        tag,
+        typ,
+        count,
+        data,
+    }
+}
+
+fn parse_strips(bytes: &[u8], order: ByteOrder, ifd: &Ifd) -> Vec<Vec<u8>> {
+    let Some((offsets_tag, counts_tag)) = ifd.data_tags() else {
+        return Vec::new();
+    };
+    let offsets = ifd.entry(offsets_tag).map(|e| e.values(order)).unwrap_or_default();
+    let counts = ifd.entry(counts_tag).map(|e| e.values(order)).unwrap_or_default();
+
+    offsets
+        .iter()
+        .enumerate()
+        .take(MAX_STRIPS)
+        .map(|(idx, offset)| {
+            let offset = (*offset as usize).min(bytes.len());
+            // Without a byte count, the strip runs to the end of the file
+            let count = counts.get(idx).map_or(usize::MAX, |count| *count as usize);
+            bytes[offset..][..count.min(bytes.len() - offset)].to_vec()
+        })
+        .collect()
+}
+
+/// The entries of `ifd`, with the offset and byte count entries matching its strips
+fn layout_entries(ifd: &Ifd, order: ByteOrder) -> Vec<Entry> {
+    let mut entries = ifd.entries.clone();
+    let Some((offsets_tag, counts_tag)) = ifd.data_tags() else {
+        return entries;
+    };
+    let count = ifd.strips.len() as u32;
+
+    let mut counts = Entry::new(counts_tag, TYPE_LONG, count);
+    for (idx, strip) in ifd.strips.iter().enumerate() {
+        counts.set_element(order, idx, strip.len() as u64);
+    }
+    for entry in &mut entries {
+        if entry.tag == offsets_tag {
+            *entry = Entry::new(offsets_tag, TYPE_LONG, count);
+        } else if entry.tag == counts_tag {
+            *entry = counts.clone();
+        }
+    }
+    if !entries.iter().any(|entry| entry.tag == counts_tag) {
+        entries.push(counts);
+    }
+    entries
+}
+
+/// Values are word aligned, as the spec asks for
+fn align(out: &mut Vec<u8>) {
+    if out.len() % 2 != 0 {
+        out.push(0);
+    }
+}
+
+fn below<R: Rand>(rand: &mut R, n: usize) -> usize {
+    rand.below(NonZeroUsize::new(n).unwrap_or(nonzero!(1)))
+}
+
+/// Parse the input, let `mutate` change the model and write it back
+fn mutate_tiff<S, F>(state: &mut S, input: &mut BytesInput, mutate: F) -> Result<MutationResult, Error>
+where
+    S: HasRand + HasMaxSize,
+    F: FnOnce(&mut Tiff, &mut S::Rand) -> MutationResult,
+{
+    let bytes = input.target_bytes();
+    let (data, schedule) = FaultSchedule::split(&bytes);
+    let has_schedule = data.len() != bytes.len();
+    let Some(mut tiff) = Tiff::parse(data) else {
+        return Ok(MutationResult::Skipped);
+    };
+    if mutate(&mut tiff, state.rand_mut()) == MutationResult::Skipped {
+        return Ok(MutationResult::Skipped);
+    }
+
+    let mut mutated = tiff.serialize();
+    if has_schedule {
+        mutated = schedule.join(&mutated);
+    }
+    if mutated.len() > state.max_size() {
+        return Ok(MutationResult::Skipped);
+    }
+    drop(bytes);
+    *input = BytesInput::new(mutated);
+    Ok(MutationResult::Mutated)
+}
+
+/// A random entry of a random IFD
+fn pick_entry<'a, R: Rand>(tiff: &'a mut Tiff, rand: &mut R) -> Option<&'a mut Entry> {
+    let ifd = below(rand, tiff.ifds.len());
+    let entries = &mut tiff.ifds[ifd].entries;
+    if entries.is_empty() {
+        return None;
+    }
+    let idx = below(rand, entries.len());
+    entries.get_mut(idx)
+}
+
+macro_rules! impl_named {
+    ($name:ident) => {
+        impl Named for $name {
+            fn name(&self) -> &Cow<'static, str> {
+                static NAME: Cow<'static, str> = Cow::Borrowed(stringify!($name));
+                &NAME
+            }
+        }
+    };
+}
+
+/// Sets a value of an entry to an interesting or a nearby value
+#[derive(Debug, Default)]
+pub struct TiffValueMutator;
+
+impl<S> Mutator<BytesInput, S> for TiffValueMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        mutate_tiff(state, input, |tiff, rand| {
+            let order = tiff.order;
+            let Some(entry) = pick_entry(tiff, rand) else {
+                return MutationResult::Skipped;
+            };
+            if entry.elements() == 0 {
+                return MutationResult::Skipped;
+            }
+            let idx = below(rand, entry.elements());
+            let old = entry.element(order, idx);
+            let value = match rand.below(nonzero!(3)) {
+                0 => rand.choose(INTERESTING).unwrap(),
+                1 => old.wrapping_add(rand.below(nonzero!(33)) as u64).wrapping_sub(16),
+                _ => rand.next(),
+            };
+            entry.set_element(order, idx, value);
+            MutationResult::Mutated
+        })
+    }
+}
+
+impl_named!(TiffValueMutator);
+
+/// Changes the tag, the type or the count of an entry
+#[derive(Debug, Default)]
+pub struct TiffEntryMutator;
+
+impl<S> Mutator<BytesInput, S> for TiffEntryMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        mutate_tiff(state, input, |tiff, rand| {
+            let Some(entry) = pick_entry(tiff, rand) else {
+                return MutationResult::Skipped;
+            };
+            match rand.below(nonzero!(3)) {
+                0 => entry.tag = rand.choose(KNOWN_TAGS).unwrap().0,
+                1 => {
+                    let typ = rand.below(nonzero!(13)) as u16 + 1;
+                    entry.resize(typ, entry.count);
+                }
+                _ => {
+                    let count = match rand.below(nonzero!(3)) {
+                        0 => 0,
+                        1 => entry.count.saturating_add(1),
+                        _ => rand.below(nonzero!(64)) as u32,
+                    };
+                    entry.resize(entry.typ, count);
+                }
+            }
+            MutationResult::Mutated
+        })
+    }
+}
+
+impl_named!(TiffEntryMutator);
+
+/// Inserts a known tag, removes or duplicates an entry, or shuffles the entry order
+#[derive(Debug, Default)]
+pub struct TiffEntryInsertRemoveMutator;
+
+impl<S> Mutator<BytesInput, S> for TiffEntryInsertRemoveMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        mutate_tiff(state, input, |tiff, rand| {
+            let order = tiff.order;
+            let ifd = below(rand, tiff.ifds.len());
+            let entries = &mut tiff.ifds[ifd].entries;
+            match rand.below(nonzero!(4)) {
+                0 if entries.len() < MAX_ENTRIES => {
+                    let (tag, typ) = rand.choose(KNOWN_TAGS).unwrap();
+                    let mut entry = Entry::new(tag, typ, rand.below(nonzero!(4)) as u32 + 1);
+                    for idx in 0..entry.elements() {
+                        entry.set_element(order, idx, rand.choose(INTERESTING).unwrap());
+                    }
+                    entries.push(entry);
+                    // libtiff only warns about unsorted entries, keep some of them unsorted
+                    if rand.coinflip(0.8) {
+                        entries.sort_by_key(|entry| entry.tag);
+                    }
+                }
+                1 if !entries.is_empty() => {
+                    let idx = below(rand, entries.len());
+                    entries.remove(idx);
+                }
+                2 if !entries.is_empty() && entries.len() < MAX_ENTRIES => {
+                    let idx = below(rand, entries.len());
+                    let entry = entries[idx].clone();
+                    entries.insert(idx, entry);
+                }
+                3 if entries.len() > 1 => {
+                    let (a, b) = (below(rand, entries.len()), below(rand, entries.len()));
+                    entries.swap(a, b);
+                }
+                _ => return MutationResult::Skipped,
+            }
+            MutationResult::Mutated
+        })
+    }
+}
+
+impl_named!(TiffEntryInsertRemoveMutator);
+
+/// Splits, merges, drops, duplicates or corrupts strips / tiles, their offsets and byte
+/// counts follow
+#[derive(Debug, Default)]
+pub struct TiffStripMutator;
+
+impl<S> Mutator<BytesInput, S> for TiffStripMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        mutate_tiff(state, input, |tiff, rand| {
+            let ifd = below(rand, tiff.ifds.len());
+            let strips = &mut tiff.ifds[ifd].strips;
+            if strips.is_empty() {
+                return MutationResult::Skipped;
+            }
+            let idx = below(rand, strips.len());
+            match rand.below(nonzero!(5)) {
+                0 if strips[idx].len() > 1 && strips.len() < MAX_STRIPS => {
+                    let at = below(rand, strips[idx].len() - 1) + 1;
+                    let tail = strips[idx].split_off(at);
+                    strips.insert(idx + 1, tail);
+                }
+                1 if idx + 1 < strips.len() => {
+                    let next = strips.remove(idx + 1);
+                    strips[idx].extend_from_slice(&next);
+                }
+                2 if strips.len() > 1 => {
+                    strips.remove(idx);
+                }
+                3 if strips.len() < MAX_STRIPS => {
+                    let strip = strips[idx].clone();
+                    strips.insert(idx, strip);
+                }
+                _ => {
+                    let strip = &mut strips[idx];
+                    if strip.is_empty() || rand.coinflip(0.2) {
+                        let len = below(rand, strip.len() + 1);
+                        strip.truncate(len);
+                    } else {
+                        let pos = below(rand, strip.len());
+                        strip[pos] = rand.next() as u8;
+                    }
+                }
+            }
+            MutationResult::Mutated
+        })
+    }
+}
+
+impl_named!(TiffStripMutator);
+
+/// Duplicates, drops or swaps IFDs (pages), or swaps the byte order of the whole file
+#[derive(Debug, Default)]
+pub struct TiffIfdMutator;
+
+impl<S> Mutator<BytesInput, S> for TiffIfdMutator
+where
+    S: HasRand + HasMaxSize,
+{
+    fn mutate(&mut self, state: &mut S, input: &mut BytesInput) -> Result<MutationResult, Error> {
+        mutate_tiff(state, input, |tiff, rand| {
+            if rand.coinflip(0.25) {
+                tiff.swap_byte_order();
+                return MutationResult::Mutated;
+            }
+            let ifds = &mut tiff.ifds;
+            let idx = below(rand, ifds.len());
+            match rand.below(nonzero!(3)) {
+                0 if ifds.len() < MAX_IFDS => {
+                    let ifd = ifds[idx].clone();
+                    ifds.insert(idx, ifd);
+                }
+                1 if ifds.len() > 1 => {
+                    ifds.remove(idx);
+                }
+                2 if ifds.len() > 1 => {
+                    let other = below(rand, ifds.len());
+                    ifds.swap(idx, other);
+                }
+                _ => return MutationResult::Skipped,
+            }
+            MutationResult::Mutated
+        })
+    }
+}
+
+impl_named!(TiffIfdMutator);
+
+/// All TIFF mutations, to be scheduled like `havoc_mutations`
+pub type TiffMutationsType = tuple_list_type!(
+    TiffValueMutator,
+    TiffValueMutator,
+    TiffEntryMutator,
+    TiffEntryInsertRemoveMutator,
+    TiffStripMutator,
+    TiffIfdMutator
+);
+
+/// Values are the most common target, so they are scheduled twice as often
+pub fn tiff_mutations() -> TiffMutationsType {
+    tuple_list!(
+        TiffValueMutator,
+        TiffValueMutator,
+        TiffEntryMutator,
+        TiffEntryInsertRemoveMutator,
+        TiffStripMutator,
+        TiffIfdMutator
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two IFDs: an image in two strips, with out-of-line values, and a thumbnail without data
+    fn sample(order: ByteOrder) -> Tiff {
+        let mut width = Entry::new(256, TYPE_LONG, 1);
+        width.set_element(order, 0, 640);
+        let mut bits = Entry::new(258, TYPE_SHORT, 3);
+        for idx in 0..3 {
+            bits.set_element(order, idx, 8);
+        }
+        let description = Entry {
+            tag: 270,
+            typ: 2,
+            count: 12,
+            data: b"hello world\0".to_vec(),
+        };
+        let image = Ifd {
+            entries: vec![
+                width,
+                bits,
+                Entry::new(TAG_STRIP_OFFSETS, TYPE_LONG, 2),
+                Entry::new(TAG_STRIP_BYTE_COUNTS, TYPE_LONG, 2),
+                description,
+            ],
+            strips: vec![b"first strip".to_vec(), b"2nd".to_vec()],
+        };
+
+        let mut subfile = Entry::new(254, TYPE_LONG, 1);
+        subfile.set_element(order, 0, 1);
+        let thumbnail = Ifd {
+            entries: vec![subfile],
+            strips: Vec::new(),
+        };
+        Tiff {
+            order,
+            ifds: vec![image, thumbnail],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for order in [ByteOrder::Little, ByteOrder::Big] {
+            let tiff = sample(order);
+            let bytes = tiff.serialize();
+            let parsed = Tiff::parse(&bytes).unwrap();
+
+            assert_eq!(parsed.order, order);
+            assert_eq!(parsed.ifds.len(), 2);
+            let image = &parsed.ifds[0];
+            assert_eq!(image.strips, tiff.ifds[0].strips);
+            assert_eq!(image.entries[0], tiff.ifds[0].entries[0]);
+            assert_eq!(image.entries[1].values(order), [8, 8, 8]);
+            assert_eq!(image.entry(270), tiff.ifds[0].entry(270));
+            assert_eq!(
+                image.entry(TAG_STRIP_BYTE_COUNTS).unwrap().values(order),
+                [11, 3]
+            );
+            assert_eq!(parsed.ifds[1], tiff.ifds[1]);
+
+            // Offsets are recomputed, a second round doesn't change the layout
+            assert_eq!(parsed.serialize(), bytes);
+        }
+    }
+
+    #[test]
+    fn byte_counts_are_added() {
+        let mut tiff = sample(ByteOrder::Little);
+        tiff.ifds[0].entries.retain(|entry| entry.tag != TAG_STRIP_BYTE_COUNTS);
+        let parsed = Tiff::parse(&tiff.serialize()).unwrap();
+        assert_eq!(parsed.ifds[0].strips, tiff.ifds[0].strips);
+        assert_eq!(
+            parsed.ifds[0]
+                .entry(TAG_STRIP_BYTE_COUNTS)
+                .unwrap()
+                .values(ByteOrder::Little),
+            [11, 3]
+        );
+    }
+
+    #[test]
+    fn swap_byte_order_keeps_values() {
+        let tiff = sample(ByteOrder::Little);
+        let mut swapped = tiff.clone();
+        swapped.swap_byte_order();
+        assert_eq!(swapped.order, ByteOrder::Big);
+        assert_eq!(swapped.ifds[0].entries[0].values(ByteOrder::Big), [640]);
+        assert_eq!(swapped.ifds[0].entries[1].values(ByteOrder::Big), [8, 8, 8]);
+
+        let parsed = Tiff::parse(&swapped.serialize()).unwrap();
+        assert_eq!(parsed.ifds[0].strips, tiff.ifds[0].strips);
+        swapped.swap_byte_order();
+        assert_eq!(swapped, tiff);
+    }
+
+    #[test]
+    fn parse_rejects_and_bounds() {
+        assert!(Tiff::parse(b"\x89PNG\r\n\x1a\n").is_none());
+        // BigTIFF
+        assert!(Tiff::parse(b"II+\0\x08\0\0\0").is_none());
+        // No IFD
+        assert!(Tiff::parse(b"II*\0\0\0\0\0").is_none());
+
+        // An IFD pointing to itself is only parsed once
+        let mut bytes = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        bytes.extend_from_slice(&[0x00, 0x01, 0x04, 0x00, 0x01, 0, 0, 0, 0x80, 0, 0, 0]);
+        bytes.extend_from_slice(&[0x08, 0, 0, 0]);
+        let tiff = Tiff::parse(&bytes).unwrap();
+        assert_eq!(tiff.ifds.len(), 1);
+        assert_eq!(tiff.ifds[0].entries[0].values(ByteOrder::Little), [0x80]);
+    }
+
+    #[test]
+    fn truncated_values_are_cut() {
+        let mut tiff = sample(ByteOrder::Big);
+        tiff.ifds.truncate(1);
+        let mut bytes = tiff.serialize();
+        // The second strip is the last thing in the file
+        bytes.truncate(bytes.len() - 1);
+        let parsed = Tiff::parse(&bytes).unwrap();
+        assert_eq!(parsed.ifds[0].strips[1], b"2n");
+    }
+}
@@ -1,12 +1,27 @@
 use core::time::Duration;
-use std::{env, ops::Range, path::PathBuf};
-
-use clap::{error::ErrorKind, CommandFactory, Parser};
+use std::{
+    collections::HashSet,
+    env, fs,
+    net::SocketAddr,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use clap::{builder::RangedU64ValueParser, error::ErrorKind, CommandFactory, Parser};
 use libafl::{events::ClientDescription, Error};
-use libafl_bolts::core_affinity::{CoreId, Cores};
+use libafl_bolts::{
+    core_affinity::{CoreId, Cores},
+    hash_std,
+};
 use libafl_qemu::GuestAddr;
 
-use crate::version::Version;
+use crate::{
+    modules::exec_trace::TraceFormat,
+    mutators::{MOptParams, MutatorStrategy},
+    scheduler::{PowerScheduleKind, SchedulerKind},
+    tmin::TminMode,
+    version::Version,
+};
 
 #[readonly::make]
 #[derive(Parser, Debug)]
@@ -27,6 +42,54 @@ pub struct FuzzerOptions {
     #[arg(short = 'x', long, help = "Tokens file")]
     pub tokens: Option<String>,
 
+    #[arg(
+        long,
+        help = "Add tokens extracted from the target: .rodata strings, compare immediates and strings of linked libraries"
+    )]
+    pub auto_dict: bool,
+
+    #[arg(long, help = "Write the extracted tokens to this file, in the format of -x", requires = "auto_dict")]
+    pub dump_dict: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Promote operands the cmplog cores keep seeing in comparisons to tokens, and share them with all clients"
+    )]
+    pub cmplog_tokens: bool,
+
+    #[arg(
+        long,
+        help = "Add a Redqueen stage to the cmplog cores: colorization, then input-to-state replacement with arithmetic and text encodings"
+    )]
+    pub redqueen: bool,
+
+    #[arg(long, help = "Main mutator of the cores without cmplog", value_enum, default_value_t = MutatorStrategy::Havoc)]
+    pub mutator: MutatorStrategy,
+
+    #[arg(long, help = "Main mutator of the cmplog cores", value_enum, default_value_t = MutatorStrategy::Mopt)]
+    pub cmplog_mutator: MutatorStrategy,
+
+    #[arg(long, help = "MOpt stacks up to 2^N mutations", default_value_t = 7, value_parser = RangedU64ValueParser::<usize>::new().range(1..64))]
+    pub mopt_stack_pow: usize,
+
+    #[arg(long, help = "Number of MOpt swarms", default_value_t = 5, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub mopt_swarms: usize,
+
+    #[arg(long, help = "Trim new corpus entries once, as long as they keep their path")]
+    pub trim: bool,
+
+    #[arg(long, help = "Corpus scheduler", value_enum, default_value_t = SchedulerKind::Queue)]
+    pub scheduler: SchedulerKind,
+
+    #[arg(long, help = "Power schedule, for the energy of the power stage and the queue / weighted schedulers", value_enum, default_value_t = PowerScheduleKind::Fast)]
+    pub power_schedule: PowerScheduleKind,
+
+    #[arg(long, help = "Corpus scheduler of the cmplog cores (default: --scheduler)", value_enum)]
+    pub cmplog_scheduler: Option<SchedulerKind>,
+
+    #[arg(long, help = "Power schedule of the cmplog cores (default: --power-schedule)", value_enum)]
+    pub cmplog_power_schedule: Option<PowerScheduleKind>,
+
     #[cfg(feature = "injections")]
     #[arg(
         short = 'j',
@@ -47,9 +110,21 @@ pub struct FuzzerOptions {
     #[arg(long, help = "Timeout in milliseconds", default_value = "1000", value_parser = FuzzerOptions::parse_timeout)]
     pub timeout: Duration,
 
+    #[arg(long, help = "Timeout in milliseconds used to re-verify timeouts before storing them as hangs (default: 4x --timeout)", value_parser = FuzzerOptions::parse_timeout)]
+    pub hang_timeout: Option<Duration>,
+
     #[arg(long = "port", help = "Broker port", default_value_t = 1337_u16)]
     pub port: u16,
 
+    #[arg(long, help = "Connect the local broker to the broker of another node, e.g. 192.168.1.10:1337")]
+    pub remote_broker_addr: Option<SocketAddr>,
+
+    #[arg(long, help = "Only run the broker on this node, without any fuzzing clients", conflicts_with_all = ["rerun_input", "cmin", "tmin"])]
+    pub broker_only: bool,
+
+    #[arg(long, help = "Broadcast crashes to all clients and nodes, which re-execute and store them")]
+    pub share_objectives: bool,
+
     #[arg(long, help = "Cpu cores to use", default_value = "all", value_parser = Cores::from_cmdline)]
     pub cores: Cores,
 
@@ -68,6 +143,31 @@ pub struct FuzzerOptions {
     #[clap(long, help = "Enable AFL++ style output", conflicts_with = "verbose")]
     pub tui: bool,
 
+    #[arg(
+        long,
+        help = "Log filter on top of RUST_LOG, with per-component levels for harness, injector, register and feedback (e.g. info,injector=trace)"
+    )]
+    pub log_filter: Option<String>,
+
+    #[arg(
+        long,
+        help = "Keep the last N execution events and attach them to the metadata of each crash, 0 disables",
+        default_value_t = 0
+    )]
+    pub exec_ring: usize,
+
+    #[arg(
+        long = "foreign-sync",
+        help = "AFL++ output or queue directory to import inputs from, can be given multiple times"
+    )]
+    pub foreign_sync: Vec<PathBuf>,
+
+    #[arg(long, help = "Export the queue with AFL++ naming into this sync directory")]
+    pub afl_export: Option<PathBuf>,
+
+    #[arg(long, help = "Interval in seconds between syncs with foreign fuzzers", default_value = "30", value_parser = FuzzerOptions::parse_seconds)]
+    pub sync_interval: Duration,
+
     #[arg(long = "iterations", help = "Maximum number of iterations")]
     pub iterations: Option<u64>,
 
@@ -89,6 +189,113 @@ pub struct FuzzerOptions {
     )]
     pub rerun_input: Option<PathBuf>,
 
+    #[arg(
+        long,
+        help = "Write a trace of the executed basic blocks for the input given with -r",
+        requires = "rerun_input",
+        conflicts_with = "drcov"
+    )]
+    pub trace: Option<PathBuf>,
+
+    #[arg(long, help = "Also trace memory reads and writes", requires = "trace")]
+    pub trace_mem: bool,
+
+    #[arg(long, help = "Also trace syscalls with their arguments and results", requires = "trace")]
+    pub trace_syscalls: bool,
+
+    #[arg(long, help = "Format of the trace", value_enum, default_value_t = TraceFormat::Jsonl)]
+    pub trace_format: TraceFormat,
+
+    #[arg(
+        long,
+        help = "Write the decoded syscalls of the input given with -r to this file, like strace",
+        requires = "rerun_input"
+    )]
+    pub strace: Option<PathBuf>,
+
+    #[arg(long, help = "Also keep inputs that trigger new syscall / argument combinations")]
+    pub syscall_coverage: bool,
+
+    #[arg(long, help = "Also keep inputs that make more operand bits of a comparison equal (value profile)")]
+    pub cmp_coverage: bool,
+
+    #[arg(
+        long,
+        help = "Let a schedule at the end of each input fail reads, anonymous mmaps and openat of the target"
+    )]
+    pub fault_injection: bool,
+
+    #[arg(
+        long,
+        help = "Let the fault schedule at the end of each input fail malloc, calloc and realloc of the guest libc"
+    )]
+    pub alloc_failure: bool,
+
+    #[arg(
+        long,
+        help = "Add a stage of structure-aware TIFF mutations (IFD entries, strips, byte order) next to havoc"
+    )]
+    pub tiff: bool,
+
+    #[arg(
+        long,
+        help = "Add a stage of structure-aware mutations for the format described in this TOML spec, and fix up checksums after havoc"
+    )]
+    pub format: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Recompute checksums of each mutated input with the rules from this TOML file"
+    )]
+    pub checksums: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Patch out comparisons that look like checksum checks while fuzzing"
+    )]
+    pub checksum_patch: bool,
+
+    #[arg(
+        long,
+        help = "Block or fake dangerous syscalls of the target, with the policy from this TOML file or the built-in one",
+        num_args = 0..=1
+    )]
+    pub sandbox: Option<Option<PathBuf>>,
+
+    #[arg(
+        long,
+        help = "Serve the replayed input to a GDB remote client on this port, stopped at start_pc. Requires -r. Only x86_64 and aarch64 targets.",
+        requires = "rerun_input",
+        value_parser = FuzzerOptions::parse_gdb_port
+    )]
+    pub gdb: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Reload the queues and crashes of all clients from the output directory of a previous run"
+    )]
+    pub resume: bool,
+
+    #[arg(
+        long,
+        help = "Minimize the queues of all clients into the given directory, instead of starting to fuzz",
+        conflicts_with = "rerun_input"
+    )]
+    pub cmin: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Minimize the given input, instead of starting to fuzz",
+        conflicts_with_all = ["rerun_input", "cmin"]
+    )]
+    pub tmin: Option<PathBuf>,
+
+    #[arg(long, help = "Output file for the minimized input (default: <tmin>.min)", requires = "tmin")]
+    pub tmin_output: Option<PathBuf>,
+
+    #[arg(long, help = "What the minimized input has to preserve", value_enum, default_value_t = TminMode::Crash)]
+    pub tmin_mode: TminMode,
+
     #[arg(last = true, help = "Arguments passed to the target")]
     pub args: Vec<String>,
 }
@@ -98,6 +305,21 @@ impl FuzzerOptions {
         Ok(Duration::from_millis(src.parse()?))
     }
 
+    fn parse_seconds(src: &str) -> Result<Duration, Error> {
+        Ok(Duration::from_secs(src.parse()?))
+    }
+
+    /// The stub only knows the registers and branches of x86_64 and aarch64
+    fn parse_gdb_port(src: &str) -> Result<u16, Error> {
+        if cfg!(not(any(feature = "x86_64", feature = "aarch64"))) {
+            return Err(Error::illegal_argument(format!(
+                "--gdb is only supported for x86_64 and aarch64 targets, not {}",
+                env!("CPU_TARGET")
+            )));
+        }
+        Ok(src.parse()?)
+    }
+
     fn parse_ranges(src: &str) -> Result<Range<GuestAddr>, Error> {
         let parts = src.split('-').collect::<Vec<&str>>();
         if parts.len() == 2 {
@@ -157,6 +379,161 @@ impl FuzzerOptions {
         dir
     }
 
+    pub fn hangs_dir(&self, client_description: ClientDescription) -> PathBuf {
+        let mut dir = self.output_dir(client_description).clone();
+        dir.push("hangs");
+        dir
+    }
+
+    pub fn hang_timeout(&self) -> Duration {
+        self.hang_timeout.unwrap_or(self.timeout * 4)
+    }
+
+    /// Whether we only execute some inputs and exit, instead of fuzzing
+    pub fn is_single_run(&self) -> bool {
+        self.rerun_input.is_some() || self.cmin.is_some() || self.tmin.is_some()
+    }
+
+    pub fn mutator_of(&self, core_id: CoreId) -> MutatorStrategy {
+        if self.is_cmplog_core(core_id) {
+            self.cmplog_mutator
+        } else {
+            self.mutator
+        }
+    }
+
+    pub fn scheduler_of(&self, core_id: CoreId) -> SchedulerKind {
+        match self.cmplog_scheduler {
+            Some(scheduler) if self.is_cmplog_core(core_id) => scheduler,
+            _ => self.scheduler,
+        }
+    }
+
+    pub fn power_schedule_of(&self, core_id: CoreId) -> PowerScheduleKind {
+        match self.cmplog_power_schedule {
+            Some(schedule) if self.is_cmplog_core(core_id) => schedule,
+            _ => self.power_schedule,
+        }
+    }
+
+    pub fn mopt_params(&self) -> MOptParams {
+        MOptParams {
+            max_stack_pow: self.mopt_stack_pow,
+            swarms: self.mopt_swarms,
+        }
+    }
+
+    /// The `-L` directory of QEMU in the target arguments, or `QEMU_LD_PREFIX`
+    pub fn rootfs(&self) -> Option<PathBuf> {
+        self.args
+            .iter()
+            .position(|arg| arg == "-L")
+            .and_then(|idx| self.args.get(idx + 1))
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("QEMU_LD_PREFIX").map(PathBuf::from))
+    }
+
+    pub fn tmin_output(&self) -> Option<PathBuf> {
+        self.tmin_output.clone().or_else(|| {
+            self.tmin.as_ref().map(|input| {
+                let mut output = input.clone().into_os_string();
+                output.push(".min");
+                PathBuf::from(output)
+            })
+        })
+    }
+
+    /// All `client_xxx/<name>` directories found below the output directory
+    fn client_dirs(&self, name: &str) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.output) else {
+            return Vec::new();
+        };
+
+        let mut dirs = entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().starts_with("client_"))
+            .map(|e| e.path().join(name))
+            .filter(|p| p.is_dir())
+            .collect::<Vec<PathBuf>>();
+        dirs.sort();
+        dirs
+    }
+
+    /// The queues of all clients of a previous run
+    pub fn queue_dirs(&self) -> Vec<PathBuf> {
+        self.client_dirs("queue")
+    }
+
+    /// The queues `--resume` reloads, see [`Self::rotate_queues`]
+    pub fn resumed_queue_dirs(&self) -> Vec<PathBuf> {
+        self.client_dirs(RESUMED_QUEUE)
+    }
+
+    /// Move the queues of the previous run aside before the clients start. The entries the
+    /// clients reload are written to their fresh queues again, instead of being copied next to
+    /// themselves.
+    pub fn rotate_queues(&self) -> Result<(), Error> {
+        for queue in self.queue_dirs() {
+            rotate_queue(&queue)?;
+        }
+        Ok(())
+    }
+
+    /// The crashes of all clients of a previous run
+    pub fn crashes_dirs(&self) -> Vec<PathBuf> {
+        self.client_dirs("crashes")
+    }
+
+    /// All corpus files in `dirs`, skipping the hidden lock and metadata files
+    /// the on-disk corpora keep next to each entry.
+    pub fn corpus_files(dirs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+        for dir in dirs {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let hidden = path
+                    .file_name()
+                    .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+                if path.is_file() && !hidden {
+                    files.push(path);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// The `queue/` directories of all foreign fuzzers below the `--foreign-sync` directories.
+    /// Accepts a queue directory, an AFL++ instance directory or a whole AFL++ sync directory,
+    /// skipping the instances we export to ourselves.
+    pub fn foreign_queue_dirs(sync_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        for dir in sync_dirs {
+            if dir.ends_with("queue") {
+                dirs.push(dir.clone());
+            } else if dir.join("queue").is_dir() {
+                dirs.push(dir.join("queue"));
+            } else if let Ok(entries) = fs::read_dir(dir) {
+                dirs.extend(
+                    entries
+                        .filter_map(Result::ok)
+                        .filter(|e| !e.file_name().to_string_lossy().starts_with("libafl_"))
+                        .map(|e| e.path().join("queue"))
+                        .filter(|p| p.is_dir()),
+                );
+            }
+        }
+        dirs
+    }
+
+    pub fn afl_export_dir(&self, client_description: ClientDescription) -> Option<PathBuf> {
+        self.afl_export.as_ref().map(|dir| {
+            let mut dir = dir.clone();
+            dir.push(format!("libafl_{:03}", client_description.id()));
+            dir.push("queue");
+            dir
+        })
+    }
+
     pub fn validate(&self) {
         if let Some(asan_cores) = &self.asan_cores {
             for id in &asan_cores.ids {
@@ -190,6 +567,16 @@ impl FuzzerOptions {
             }
         }
 
+        let structure = [self.mutator, self.cmplog_mutator].contains(&MutatorStrategy::Structure);
+        if structure && self.format.is_none() && !self.tiff {
+            let mut cmd = FuzzerOptions::command();
+            cmd.error(
+                ErrorKind::MissingRequiredArgument,
+                "The `structure` mutator needs `--format` or `--tiff`.".to_string(),
+            )
+            .exit();
+        }
+
         if self.drcov.is_some() && self.rerun_input.is_none() {
             let mut cmd = FuzzerOptions::command();
             cmd.error(
@@ -200,3 +587,76 @@ impl FuzzerOptions {
         }
     }
 }
+
+/// Where `--resume` keeps the queue of a client of the previous runs
+const RESUMED_QUEUE: &str = "queue.resumed";
+
+/// Move the entries of `queue` to the resumed queue next to it, except those a previous resume
+/// already kept there, and remove `queue` with the lock files of its on-disk corpus
+fn rotate_queue(queue: &Path) -> Result<(), Error> {
+    let resumed = queue.with_file_name(RESUMED_QUEUE);
+    fs::create_dir_all(&resumed)?;
+
+    let mut known = HashSet::new();
+    for file in FuzzerOptions::corpus_files(std::slice::from_ref(&resumed))? {
+        known.insert(hash_std(&fs::read(file)?));
+    }
+    let mut moved = 0;
+    for file in FuzzerOptions::corpus_files(&[queue.to_path_buf()])? {
+        let hash = hash_std(&fs::read(&file)?);
+        if !known.insert(hash) {
+            continue;
+        }
+        let mut target = resumed.join(file.file_name().unwrap_or_default());
+        if target.exists() {
+            target = resumed.join(format!("{hash:016x}"));
+        }
+        fs::rename(&file, target)?;
+        moved += 1;
+    }
+    fs::remove_dir_all(queue)?;
+    log::info!("Moved {moved} new entries of {queue:?} to {resumed:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumed_queues_do_not_grow() {
+        let output = env::temp_dir().join(format!("rotate-queue-{}", std::process::id()));
+        let queue = output.join("client_0").join("queue");
+        let resumed = output.join("client_0").join(RESUMED_QUEUE);
+        let write = |files: &[(&str, &[u8])]| {
+            fs::create_dir_all(&queue).unwrap();
+            for (name, bytes) in files {
+                fs::write(queue.join(name), bytes).unwrap();
+            }
+        };
+        let entries = || {
+            let mut files = FuzzerOptions::corpus_files(std::slice::from_ref(&resumed)).unwrap();
+            files.sort();
+            files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        write(&[("a", b"a"), ("b", b"b"), (".a.lafl_lock", b"1")]);
+        rotate_queue(&queue).unwrap();
+        assert!(!queue.exists());
+        assert_eq!(entries(), ["a", "b"]);
+
+        // The resumed run writes the reloaded entries to its queue again, next to new ones
+        write(&[("a", b"a"), ("a-1", b"a"), ("b", b"b"), ("c", b"c")]);
+        rotate_queue(&queue).unwrap();
+        assert_eq!(entries(), ["a", "b", "c"]);
+
+        write(&[("a", b"a"), ("b", b"b"), ("c", b"changed")]);
+        rotate_queue(&queue).unwrap();
+        assert_eq!(entries().len(), 4);
+
+        fs::remove_dir_all(output).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+//! The corpus scheduler and power schedule of a core, chosen with `--scheduler` and
+//! `--power-schedule` (and their `--cmplog-` variants).
+use clap::ValueEnum;
+use libafl::{
+    corpus::{Corpus, CorpusId, SchedulerTestcaseMetadata, Testcase},
+    inputs::BytesInput,
+    schedulers::{
+        powersched::PowerSchedule, testcase_score::TestcaseScore, RemovableScheduler, Scheduler,
+    },
+    state::HasCorpus,
+    Error, HasMetadata,
+};
+use libafl_bolts::tuples::MatchName;
+
+use crate::instance::ClientState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SchedulerKind {
+    /// The corpus in order, with the energy of the power schedule
+    Queue,
+    /// Entries picked by weights derived from the power schedule
+    Weighted,
+    /// Entries picked with a probability that falls with their length, see [`ShortInputScore`]
+    Probability,
+    /// Entries picked uniformly at random
+    Rand,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PowerScheduleKind {
+    Explore,
+    Fast,
+    Coe,
+    Lin,
+    Quad,
+    Exploit,
+}
+
+impl PowerScheduleKind {
+    pub fn schedule(self) -> PowerSchedule {
+        match self {
+            Self::Explore => PowerSchedule::explore(),
+            Self::Fast => PowerSchedule::fast(),
+            Self::Coe => PowerSchedule::coe(),
+            Self::Lin => PowerSchedule::lin(),
+            Self::Quad => PowerSchedule::quad(),
+            Self::Exploit => PowerSchedule::exploit(),
+        }
+    }
+}
+
+/// The score of the `probability` scheduler, computed once when an entry is added (or
+/// trimmed). The power scores need the exec time and bitmap size calibration only measures
+/// after that, so shorter inputs are simply picked more often: 1 KiB half as often as 1 byte.
+#[derive(Debug)]
+pub struct ShortInputScore;
+
+impl TestcaseScore<BytesInput, ClientState> for ShortInputScore {
+    fn compute(state: &ClientState, entry: &mut Testcase<BytesInput>) -> Result<f64, Error> {
+        let len = entry.load_len(state.corpus())?;
+        Ok(1.0 / (1.0 + 0.1 * (len.max(1) as f64).log2()))
+    }
+}
+
+/// Only one of the schedulers is built, the one of [`SchedulerKind`]. This keeps the type of
+/// the fuzzer the same whatever the choice.
+#[derive(Debug)]
+pub struct CoreScheduler<Q, W, P, R> {
+    queue: Option<Q>,
+    weighted: Option<W>,
+    probability: Option<P>,
+    rand: Option<R>,
+}
+
+impl<Q, W, P, R> CoreScheduler<Q, W, P, R> {
+    pub fn new(
+        queue: Option<Q>,
+        weighted: Option<W>,
+        probability: Option<P>,
+        rand: Option<R>,
+    ) -> Self {
+        Self {
+            queue,
+            weighted,
+            probability,
+            rand,
+        }
+    }
+}
+
+macro_rules! delegate {
+    ($self:ident, $scheduler:ident => $call:expr) => {
+        if let Some($scheduler) = &mut $self.queue {
+            $call
+        } else if let Some($scheduler) = &mut $self.weighted {
+            $call
+        } else if let Some($scheduler) = &mut $self.probability {
+            $call
+        } else if let Some($scheduler) = &mut $self.rand {
+            $call
+        } else {
+            Err(Error::illegal_state("No scheduler was built"))
+        }
+    };
+}
+
+impl<Q, W, P, R> Scheduler<BytesInput, ClientState> for CoreScheduler<Q, W, P, R>
+where
+    Q: Scheduler<BytesInput, ClientState>,
+    W: Scheduler<BytesInput, ClientState>,
+    P: Scheduler<BytesInput, ClientState>,
+    R: Scheduler<BytesInput, ClientState>,
+{
+    fn on_add(&mut self, state: &mut ClientState, id: CorpusId) -> Result<(), Error> {
+        // Calibration and the power stage need the testcase metadata only the queue and
+        // weighted schedulers add
+        if self.queue.is_none() && self.weighted.is_none() {
+            let depth = match *state.corpus().current() {
+                Some(parent) => state
+                    .corpus()
+                    .get(parent)?
+                    .borrow()
+                    .metadata::<SchedulerTestcaseMetadata>()
+                    .map_or(0, SchedulerTestcaseMetadata::depth),
+                None => 0,
+            };
+            let mut testcase = state.corpus().get(id)?.borrow_mut();
+            if !testcase.has_metadata::<SchedulerTestcaseMetadata>() {
+                testcase.add_metadata(SchedulerTestcaseMetadata::new(depth + 1));
+            }
+        }
+        delegate!(self, scheduler => scheduler.on_add(state, id))
+    }
+
+    fn on_evaluation<OT>(
+        &mut self,
+        state: &mut ClientState,
+        input: &BytesInput,
+        observers: &OT,
+    ) -> Result<(), Error>
+    where
+        OT: MatchName,
+    {
+        delegate!(self, scheduler => scheduler.on_evaluation(state, input, observers))
+    }
+
+    fn next(&mut self, state: &mut ClientState) -> Result<CorpusId, Error> {
+        delegate!(self, scheduler => scheduler.next(state))
+    }
+
+    fn set_current_scheduled(
+        &mut self,
+        state: &mut ClientState,
+        next_id: Option<CorpusId>,
+    ) -> Result<(), Error> {
+        delegate!(self, scheduler => scheduler.set_current_scheduled(state, next_id))
+    }
+}
+
+impl<Q, W, P, R> RemovableScheduler<BytesInput, ClientState> for CoreScheduler<Q, W, P, R>
+where
+    Q: RemovableScheduler<BytesInput, ClientState>,
+    W: RemovableScheduler<BytesInput, ClientState>,
+    P: RemovableScheduler<BytesInput, ClientState>,
+    R: RemovableScheduler<BytesInput, ClientState>,
+{
+    fn on_remove(
+        &mut self,
+        state: &mut ClientState,
+        id: CorpusId,
+        testcase: &Option<Testcase<BytesInput>>,
+    ) -> Result<(), Error> {
+        delegate!(self, scheduler => scheduler.on_remove(state, id, testcase))
+    }
+
+    fn on_replace(
+        &mut self,
+        state: &mut ClientState,
+        id: CorpusId,
+        prev: &Testcase<BytesInput>,
+    ) -> Result<(), Error> {
+        delegate!(self, scheduler => scheduler.on_replace(state, id, prev))
+    }
+}
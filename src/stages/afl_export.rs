@@ -0,0 +1,102 @@
+use core::time::Duration;
+use std::{fs, path::PathBuf};
+
+use libafl::{
+    corpus::Corpus,
+    inputs::HasTargetBytes,
+    stages::Stage,
+    state::{HasCorpus, HasExecutions, HasStartTime},
+    Error, HasMetadata,
+};
+use libafl_bolts::{current_time, AsSlice};
+use serde::{Deserialize, Serialize};
+
+use crate::{instance::ClientState, stages::foreign_sync::ForeignSyncMeta};
+
+/// How far the queue has already been exported, kept in the state so restarts don't duplicate entries
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AflExportMeta {
+    next_corpus_idx: usize,
+}
+
+libafl_bolts::impl_serdeany!(AflExportMeta);
+
+/// Periodically copies new corpus entries into an AFL-style `queue/` directory,
+/// named `id:NNNNNN,...` so AFL++ instances pick them up when syncing. Entries imported
+/// from the foreign queues aren't handed back.
+#[derive(Debug)]
+pub struct AflExportStage {
+    queue_dir: PathBuf,
+    interval: Duration,
+    last_export: Duration,
+}
+
+impl AflExportStage {
+    pub fn new(queue_dir: PathBuf, interval: Duration) -> Self {
+        Self {
+            queue_dir,
+            interval,
+            last_export: Duration::ZERO,
+        }
+    }
+
+    fn export(&self, state: &mut ClientState) -> Result<(), Error> {
+        fs::create_dir_all(&self.queue_dir)?;
+
+        let count = state.corpus().count();
+        let mut next = state
+            .metadata_or_insert_with(AflExportMeta::default)
+            .next_corpus_idx;
+
+        let time = (current_time() - *state.start_time()).as_millis();
+        let execs = *state.executions();
+
+        while next < count {
+            let id = state.corpus().nth(next);
+            let input = state.corpus().cloned_input_for_id(id)?;
+            let bytes = input.target_bytes();
+            if state
+                .metadata::<ForeignSyncMeta>()
+                .is_ok_and(|meta| meta.is_foreign(bytes.as_slice()))
+            {
+                next += 1;
+                continue;
+            }
+            let name = format!("id:{next:06},time:{time},execs:{execs},orig:libafl");
+            fs::write(self.queue_dir.join(name), bytes.as_slice())?;
+            next += 1;
+        }
+
+        state
+            .metadata_mut::<AflExportMeta>()?
+            .next_corpus_idx = next;
+        Ok(())
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for AflExportStage {
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut ClientState,
+        _manager: &mut EM,
+    ) -> Result<(), Error> {
+        let now = current_time();
+        if now - self.last_export < self.interval {
+            return Ok(());
+        }
+        self.last_export = now;
+
+        self.export(state)
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        // Exporting twice is harmless, the metadata keeps track of the progress
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::{HashMap, HashSet};
+
+use libafl::{
+    corpus::CorpusId,
+    events::{CustomBufEventResult, Event, EventFirer},
+    inputs::BytesInput,
+    mutators::Tokens,
+    observers::cmp::{CmpValues, CmpValuesMetadata},
+    stages::Stage,
+    state::HasCurrentCorpusId,
+    Error, HasMetadata,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{autodict::immediate_tokens, instance::ClientState};
+
+/// The tag of the events carrying promoted tokens to the other clients
+pub const CMPLOG_TOKENS_TAG: &str = "cmplog_tokens";
+
+/// Corpus entries an operand has to show up in before it becomes a token
+const MIN_INPUTS: usize = 8;
+
+/// Candidates we count at most, operands of data-dependent comparisons rarely repeat
+const MAX_CANDIDATES: usize = 1 << 16;
+
+/// Tokens promoted by one client at most
+const MAX_TOKENS: usize = 4096;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CmpLogTokensMeta {
+    /// In how many traced corpus entries each operand showed up
+    counts: HashMap<Vec<u8>, usize>,
+    traced: HashSet<CorpusId>,
+    promoted: usize,
+}
+
+impl CmpLogTokensMeta {
+    /// Drop the operands seen in the fewest entries, down to half of [`MAX_CANDIDATES`], even
+    /// when most of them were seen more than once
+    fn evict(&mut self) {
+        let mut counts = self.counts.drain().collect::<Vec<(Vec<u8>, usize)>>();
+        counts.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        counts.truncate(MAX_CANDIDATES / 2 - 1);
+        self.counts = counts.into_iter().collect();
+    }
+}
+
+libafl_bolts::impl_serdeany!(CmpLogTokensMeta);
+
+/// The operands of a logged comparison, as tokens in both byte orders
+fn operand_tokens(cmp: &CmpValues) -> Vec<Vec<u8>> {
+    let operands = match cmp {
+        CmpValues::U16((v0, v1, ..)) => [i64::from(*v0 as i16), i64::from(*v1 as i16)].map(|v| (v, 2)),
+        CmpValues::U32((v0, v1, ..)) => [i64::from(*v0 as i32), i64::from(*v1 as i32)].map(|v| (v, 4)),
+        CmpValues::U64((v0, v1, ..)) => [*v0 as i64, *v1 as i64].map(|v| (v, 8)),
+        CmpValues::Bytes((v0, v1)) => {
+            return [v0.as_slice(), v1.as_slice()]
+                .into_iter()
+                .filter(|bytes| bytes.len() >= 2)
+                .map(<[u8]>::to_vec)
+                .collect();
+        }
+        _ => return Vec::new(),
+    };
+    operands
+        .into_iter()
+        .filter_map(|(value, size)| immediate_tokens(value, size))
+        .flatten()
+        .collect()
+}
+
+/// Promotes operands the cmplog tracing keeps seeing, in comparisons of different corpus
+/// entries, into the `Tokens` of this client and broadcasts them, so the clients without
+/// cmplog get to use them as well. Has to run right after the tracing stage.
+#[derive(Debug, Default)]
+pub struct CmpLogTokensStage;
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for CmpLogTokensStage
+where
+    EM: EventFirer<BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        _fuzzer: &mut Z,
+        _executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(id) = state.current_corpus_id()? else {
+            return Ok(());
+        };
+        let Some(cmps) = state.metadata_map().get::<CmpValuesMetadata>() else {
+            return Ok(());
+        };
+        let operands = cmps
+            .list
+            .iter()
+            .flat_map(operand_tokens)
+            .collect::<HashSet<Vec<u8>>>();
+
+        let meta = state.metadata_or_insert_with(CmpLogTokensMeta::default);
+        if meta.promoted >= MAX_TOKENS || !meta.traced.insert(id) {
+            return Ok(());
+        }
+        if meta.counts.len() + operands.len() > MAX_CANDIDATES {
+            meta.evict();
+        }
+        let mut promoted = Vec::new();
+        for operand in operands {
+            let count = meta.counts.entry(operand.clone()).or_default();
+            *count += 1;
+            if *count == MIN_INPUTS && meta.promoted < MAX_TOKENS {
+                meta.promoted += 1;
+                promoted.push(operand);
+            }
+        }
+        if promoted.is_empty() {
+            return Ok(());
+        }
+
+        let tokens = state.metadata_or_insert_with(Tokens::new);
+        for token in &promoted {
+            let _ = tokens.add_token(token);
+        }
+        log::info!("Promoted {} cmplog operands to tokens", promoted.len());
+        manager.fire(
+            state,
+            Event::CustomBuf {
+                buf: encode(&promoted),
+                tag: CMPLOG_TOKENS_TAG.to_string(),
+            },
+        )
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Each token prefixed by its length, tokens are short
+fn encode(tokens: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for token in tokens.iter().filter(|token| token.len() <= usize::from(u8::MAX)) {
+        buf.push(token.len() as u8);
+        buf.extend_from_slice(token);
+    }
+    buf
+}
+
+fn decode(mut buf: &[u8]) -> Vec<Vec<u8>> {
+    let mut tokens = Vec::new();
+    while let Some((len, rest)) = buf.split_first() {
+        let Some(token) = rest.get(..usize::from(*len)) else {
+            break;
+        };
+        tokens.push(token.to_vec());
+        buf = &rest[token.len()..];
+    }
+    tokens
+}
+
+/// Adds the tokens another client broadcast to ours, for the custom buf handlers of the
+/// event manager
+pub fn receive_cmplog_tokens(
+    state: &mut ClientState,
+    tag: &str,
+    buf: &[u8],
+) -> Result<CustomBufEventResult, Error> {
+    if tag != CMPLOG_TOKENS_TAG {
+        return Ok(CustomBufEventResult::Next);
+    }
+    let received = decode(buf);
+    log::debug!("Received {} cmplog tokens", received.len());
+    let tokens = state.metadata_or_insert_with(Tokens::new);
+    for token in &received {
+        let _ = tokens.add_token(token);
+    }
+    Ok(CustomBufEventResult::Handled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_keeps_the_most_seen() {
+        let mut meta = CmpLogTokensMeta::default();
+        // Every operand seen at least twice, `retain` of the seen once wouldn't drop any
+        for idx in 0..MAX_CANDIDATES {
+            meta.counts.insert((idx as u32).to_le_bytes().to_vec(), 2 + idx % 3);
+        }
+        meta.evict();
+        assert!(meta.counts.len() < MAX_CANDIDATES / 2);
+        assert!(meta.counts.values().all(|count| *count >= 3));
+        assert_eq!(meta.counts.values().filter(|count| **count == 4).count(), MAX_CANDIDATES / 3);
+    }
+}
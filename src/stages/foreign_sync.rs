@@ -0,0 +1,115 @@
+use core::time::Duration;
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use libafl::{fuzzer::Evaluator, inputs::BytesInput, stages::Stage, Error, HasMetadata};
+use libafl_bolts::{current_time, hash_std};
+use serde::{Deserialize, Serialize};
+
+use crate::{instance::ClientState, options::FuzzerOptions};
+
+/// The foreign files imported so far, kept in the state so restarts don't import them again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ForeignSyncMeta {
+    files: HashSet<PathBuf>,
+    /// Hashes of the imported inputs, which the `AflExportStage` doesn't hand back
+    inputs: HashSet<u64>,
+}
+
+impl ForeignSyncMeta {
+    /// Whether `bytes` were imported from a foreign queue
+    pub fn is_foreign(&self, bytes: &[u8]) -> bool {
+        self.inputs.contains(&hash_std(bytes))
+    }
+}
+
+libafl_bolts::impl_serdeany!(ForeignSyncMeta);
+
+/// Periodically imports new files from the `queue/` directories of the foreign fuzzers below
+/// `--foreign-sync`. The directories are looked up again every time, so AFL++ instances
+/// started after us are synced as well.
+#[derive(Debug)]
+pub struct ForeignSyncStage {
+    sync_dirs: Vec<PathBuf>,
+    interval: Duration,
+    last_sync: Duration,
+}
+
+impl ForeignSyncStage {
+    pub fn new(sync_dirs: Vec<PathBuf>, interval: Duration) -> Self {
+        Self {
+            sync_dirs,
+            interval,
+            last_sync: Duration::ZERO,
+        }
+    }
+
+    fn sync<E, EM, Z>(
+        &self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error>
+    where
+        Z: Evaluator<E, EM, BytesInput, ClientState>,
+    {
+        let mut files = Vec::new();
+        for dir in FuzzerOptions::foreign_queue_dirs(&self.sync_dirs) {
+            // An instance may remove its queue while restarting
+            if let Ok(found) = FuzzerOptions::corpus_files(&[dir]) {
+                files.extend(found);
+            }
+        }
+        let meta = state.metadata_or_insert_with(ForeignSyncMeta::default);
+        files.retain(|file| !meta.files.contains(file));
+        // AFL++ names its entries by id, the older ones first
+        files.sort();
+
+        let mut imported = 0_usize;
+        for file in files {
+            let Ok(bytes) = fs::read(&file) else {
+                continue;
+            };
+            let meta = state.metadata_mut::<ForeignSyncMeta>()?;
+            meta.files.insert(file);
+            meta.inputs.insert(hash_std(&bytes));
+
+            fuzzer.evaluate_input(state, executor, manager, BytesInput::new(bytes))?;
+            imported += 1;
+        }
+        if imported > 0 {
+            log::info!("Imported {imported} inputs from foreign queues");
+        }
+        Ok(())
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for ForeignSyncStage
+where
+    Z: Evaluator<E, EM, BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let now = current_time();
+        if now - self.last_sync < self.interval {
+            return Ok(());
+        }
+        self.last_sync = now;
+
+        self.sync(fuzzer, executor, state, manager)
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        // The metadata keeps track of the imported files
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+pub mod afl_export;
+pub mod cmplog_tokens;
+pub mod foreign_sync;
+pub mod redqueen;
+pub mod share_objectives;
+pub mod trim;
+pub mod verify_crashes;
+pub mod verify_hangs;
+
+pub use afl_export::AflExportStage;
+pub use cmplog_tokens::CmpLogTokensStage;
+pub use foreign_sync::ForeignSyncStage;
+pub use redqueen::RedQueenStage;
+pub use share_objectives::ShareObjectivesStage;
+pub use trim::TrimStage;
+pub use verify_crashes::VerifyCrashesStage;
+pub use verify_hangs::VerifyHangsStage;
@@ -0,0 +1,470 @@
+//! A Redqueen-style input-to-state stage for the cmplog cores.
+//!
+//! It runs once per corpus entry. Colorization first replaces as many input bytes as possible
+//! with random bytes of the same class (digits stay digits, letters stay letters) while the
+//! execution keeps its path. The cmplog operands of the original and the colorized run then
+//! show which input bytes end up in which operand, and how: as they are, in either byte
+//! order, with an offset added or xored, or as decimal or hex text. Each match is replaced
+//! by the other operand, encoded the same way, and the result is evaluated like any other
+//! input. Operands of `memcmp`-like routines are replaced as byte strings.
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    ops::Range,
+};
+
+use libafl::{
+    corpus::{Corpus, CorpusId},
+    executors::ExitKind,
+    fuzzer::{Evaluator, ExecutesInput},
+    inputs::{BytesInput, HasTargetBytes},
+    observers::{
+        cmp::{CmpValues, CmpValuesMetadata},
+        Observer,
+    },
+    stages::Stage,
+    state::{HasCorpus, HasCurrentCorpusId, HasMaxSize, HasRand},
+    Error, HasMetadata,
+};
+use libafl_bolts::{rands::Rand, AsSlice};
+use libafl_qemu::modules::cmplog::CmpLogObserver;
+use libafl_targets::{edges_map_mut_ptr, EDGES_MAP_DEFAULT_SIZE, MAX_EDGES_FOUND};
+use serde::{Deserialize, Serialize};
+
+use crate::{format::spec::Endian, instance::ClientState, modules::fault_schedule::FaultSchedule};
+
+/// Executions colorization may spend on one input
+const MAX_COLOR_EXECS: usize = 512;
+
+/// Distinct comparisons looked at per input
+const MAX_CMPS: usize = 256;
+
+/// Only the start of bigger inputs is searched for operands
+const MAX_SCAN: usize = 1 << 14;
+
+/// Replacements evaluated per input
+const MAX_CANDIDATES: usize = 2048;
+
+/// The corpus entries the stage already ran on
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RedQueenMeta {
+    done: HashSet<CorpusId>,
+}
+
+libafl_bolts::impl_serdeany!(RedQueenMeta);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operands {
+    Int { v0: u64, v1: u64, size: usize },
+    Bytes { v0: Vec<u8>, v1: Vec<u8> },
+}
+
+impl Operands {
+    /// Single byte comparisons match nearly everywhere, they are left to havoc
+    fn of(cmp: &CmpValues) -> Option<Self> {
+        let (v0, v1, size) = match cmp {
+            CmpValues::U16((v0, v1, ..)) => (u64::from(*v0), u64::from(*v1), 2),
+            CmpValues::U32((v0, v1, ..)) => (u64::from(*v0), u64::from(*v1), 4),
+            CmpValues::U64((v0, v1, ..)) => (*v0, *v1, 8),
+            CmpValues::Bytes((v0, v1)) => {
+                return Some(Self::Bytes {
+                    v0: v0.as_slice().to_vec(),
+                    v1: v1.as_slice().to_vec(),
+                })
+            }
+            _ => return None,
+        };
+        Some(Self::Int { v0, v1, size })
+    }
+}
+
+struct Trace {
+    /// Hash of the classified edge map
+    path: u64,
+    cmps: Vec<CmpValues>,
+}
+
+/// The input of the current entry, colorized if that worked
+struct Colorized<'a> {
+    data: &'a [u8],
+    colored: Option<(Vec<u8>, Vec<bool>)>,
+}
+
+impl Colorized<'_> {
+    fn scan_len(&self) -> usize {
+        self.data.len().min(MAX_SCAN)
+    }
+
+    /// The colorized bytes at `range`, if any byte in it was colorized
+    fn colored(&self, range: Range<usize>) -> Option<&[u8]> {
+        let (colored, tainted) = self.colored.as_ref()?;
+        tainted[range.clone()]
+            .iter()
+            .any(|tainted| *tainted)
+            .then(|| &colored[range])
+    }
+}
+
+/// A replacement of `len` bytes at `pos`
+type Edit = (usize, usize, Vec<u8>);
+
+#[derive(Default)]
+struct Edits {
+    seen: HashSet<Edit>,
+    edits: Vec<Edit>,
+}
+
+impl Edits {
+    fn push(&mut self, pos: usize, len: usize, bytes: Vec<u8>) {
+        let edit = (pos, len, bytes);
+        if self.edits.len() < MAX_CANDIDATES && self.seen.insert(edit.clone()) {
+            self.edits.push(edit);
+        }
+    }
+}
+
+fn mask(size: usize) -> u64 {
+    u64::MAX >> (64 - size * 8)
+}
+
+fn encode(endian: Endian, value: u64, size: usize) -> Vec<u8> {
+    let mut bytes = vec![0; size];
+    endian.write(&mut bytes, value);
+    bytes
+}
+
+/// Decimal, lower and upper case hex
+fn text_forms(value: u64) -> [String; 3] {
+    [value.to_string(), format!("{value:x}"), format!("{value:X}")]
+}
+
+fn find_all<'a>(data: &'a [u8], pattern: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    data.windows(pattern.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == pattern)
+        .map(|(pos, _)| pos)
+}
+
+/// Integer operands as they are, in either byte order and at smaller sizes if they fit,
+/// plus offsets and xor keys colorization confirms
+fn int_edits(input: &Colorized, orig: (u64, u64, usize), colored: Option<(u64, u64)>, edits: &mut Edits) {
+    let (o0, o1, cmp_size) = orig;
+    for (pattern, repl, colored_pattern) in [
+        (o0, o1, colored.map(|(c0, _)| c0)),
+        (o1, o0, colored.map(|(_, c1)| c1)),
+    ] {
+        if pattern == repl {
+            continue;
+        }
+        // Without colorization, only values too big to show up by chance
+        if colored_pattern.is_none() && pattern < 0x100 {
+            continue;
+        }
+
+        for size in [8, 4, 2] {
+            let fits = size == cmp_size || (pattern | repl) & !mask(size) == 0;
+            if size > cmp_size || !fits || input.scan_len() < size {
+                continue;
+            }
+            let (p, r) = (pattern & mask(size), repl & mask(size));
+            let cp = colored_pattern.map(|cp| cp & mask(size));
+
+            for endian in [Endian::Little, Endian::Big] {
+                for pos in 0..=input.scan_len() - size {
+                    let iv = endian.read(&input.data[pos..pos + size]);
+                    let civ = input.colored(pos..pos + size).map(|bytes| endian.read(bytes));
+
+                    // As it is, and off by one for `<` and `>`
+                    if iv == p && cp.map_or(true, |cp| civ.unwrap_or(iv) == cp) {
+                        for value in [r, r.wrapping_add(1), r.wrapping_sub(1)] {
+                            edits.push(pos, size, encode(endian, value & mask(size), size));
+                        }
+                    }
+
+                    // Transformed, trusted only if the colorized bytes are transformed alike
+                    let (Some(civ), Some(cp)) = (civ, cp) else {
+                        continue;
+                    };
+                    if civ == iv {
+                        continue;
+                    }
+                    let offset = iv.wrapping_sub(p) & mask(size);
+                    if offset != 0 && civ.wrapping_sub(cp) & mask(size) == offset {
+                        let value = r.wrapping_add(offset) & mask(size);
+                        edits.push(pos, size, encode(endian, value, size));
+                    }
+                    let key = iv ^ p;
+                    if key != 0 && civ ^ cp == key {
+                        edits.push(pos, size, encode(endian, r ^ key, size));
+                    }
+                }
+            }
+        }
+
+        // As decimal or hex text, not as part of a longer number
+        let data = &input.data[..input.scan_len()];
+        for (form, (p_text, r_text)) in text_forms(pattern)
+            .into_iter()
+            .zip(text_forms(repl))
+            .enumerate()
+        {
+            if p_text.len() < 2 || (colored_pattern.is_none() && p_text.len() < 4) {
+                continue;
+            }
+            let c_text = colored_pattern.map(|cp| text_forms(cp)[form].clone());
+            for pos in find_all(data, p_text.as_bytes()) {
+                let end = pos + p_text.len();
+                let bounded = (pos == 0 || !data[pos - 1].is_ascii_hexdigit())
+                    && data.get(end).map_or(true, |byte| !byte.is_ascii_hexdigit());
+                let confirmed = match (&c_text, &input.colored) {
+                    (Some(c_text), Some((colored, _))) => colored[pos..].starts_with(c_text.as_bytes()),
+                    _ => true,
+                };
+                if bounded && confirmed {
+                    edits.push(pos, p_text.len(), r_text.clone().into_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Byte strings of routines like `memcmp` and `strcmp`
+fn bytes_edits(input: &Colorized, orig: (&[u8], &[u8]), colored: Option<(&[u8], &[u8])>, edits: &mut Edits) {
+    let (o0, o1) = orig;
+    for (pattern, repl, colored_pattern) in [
+        (o0, o1, colored.map(|(c0, _)| c0)),
+        (o1, o0, colored.map(|(_, c1)| c1)),
+    ] {
+        if pattern == repl || pattern.len() < 2 || (colored_pattern.is_none() && pattern.len() < 3) {
+            continue;
+        }
+        for pos in find_all(&input.data[..input.scan_len()], pattern) {
+            let confirmed = match (colored_pattern, &input.colored) {
+                (Some(cp), Some((colored, _))) => colored[pos..].starts_with(cp),
+                _ => true,
+            };
+            if confirmed {
+                edits.push(pos, pattern.len(), repl.to_vec());
+            }
+        }
+    }
+}
+
+/// The edges of the last execution, already classified into hitcount buckets
+pub(crate) fn path_hash() -> u64 {
+    let len = unsafe { MAX_EDGES_FOUND }.min(EDGES_MAP_DEFAULT_SIZE);
+    let edges = unsafe { std::slice::from_raw_parts(edges_map_mut_ptr(), len) };
+    let mut hasher = DefaultHasher::new();
+    edges.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A random other byte of the same class, so text stays text
+fn recolor<R: Rand>(rand: &mut R, byte: u8) -> u8 {
+    let (base, len) = match byte {
+        b'0'..=b'9' => (b'0', 10),
+        b'a'..=b'z' => (b'a', 26),
+        b'A'..=b'Z' => (b'A', 26),
+        _ => (0, 256),
+    };
+    let step = 1 + rand.below(NonZeroUsize::new(len - 1).unwrap());
+    base + ((usize::from(byte - base) + step) % len) as u8
+}
+
+#[derive(Debug)]
+pub struct RedQueenStage {
+    /// Separate from the one of the tracing stage, which only the shadow executor runs
+    observer: CmpLogObserver,
+}
+
+impl Default for RedQueenStage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RedQueenStage {
+    pub fn new() -> Self {
+        Self {
+            observer: CmpLogObserver::new("redqueen", true),
+        }
+    }
+
+    /// Execute `input`, with the comparisons it makes if `cmps` is set
+    fn trace<E, EM, Z>(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+        input: &BytesInput,
+        cmps: bool,
+    ) -> Result<Option<Trace>, Error>
+    where
+        Z: ExecutesInput<E, EM, BytesInput, ClientState>,
+    {
+        if cmps {
+            self.observer.pre_exec(state, input)?;
+        }
+        let exit_kind = fuzzer.execute_input(state, executor, manager, input)?;
+        if cmps {
+            self.observer.post_exec(state, input, &exit_kind)?;
+        }
+        if exit_kind != ExitKind::Ok {
+            return Ok(None);
+        }
+        let cmps = if cmps {
+            state
+                .metadata::<CmpValuesMetadata>()
+                .map(|meta| meta.list.clone())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        Ok(Some(Trace {
+            path: path_hash(),
+            cmps,
+        }))
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for RedQueenStage
+where
+    Z: ExecutesInput<E, EM, BytesInput, ClientState> + Evaluator<E, EM, BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(id) = state.current_corpus_id()? else {
+            return Ok(());
+        };
+        if !state
+            .metadata_or_insert_with(RedQueenMeta::default)
+            .done
+            .insert(id)
+        {
+            return Ok(());
+        }
+
+        // The fault schedule is kept as it is
+        let input = state.corpus().cloned_input_for_id(id)?;
+        let bytes = input.target_bytes();
+        let (data, schedule) = FaultSchedule::split(bytes.as_slice());
+        let has_schedule = data.len() != bytes.len();
+        let wrap = |data: &[u8]| {
+            BytesInput::new(if has_schedule {
+                schedule.join(data)
+            } else {
+                data.to_vec()
+            })
+        };
+        // The i2s mutations after us use the comparisons of the tracing stage
+        let tracing_cmps = state.metadata::<CmpValuesMetadata>().map(|meta| meta.list.clone());
+
+        let Some(orig) = self.trace(fuzzer, executor, state, manager, &input, true)? else {
+            return Ok(());
+        };
+
+        // Colorize the biggest ranges first, halving those that change the path
+        let mut colored = data.to_vec();
+        let mut tainted = vec![false; data.len()];
+        let mut pending = vec![0..data.len()];
+        let mut execs = 0;
+        while let Some(range) = pending.pop() {
+            if range.is_empty() || execs >= MAX_COLOR_EXECS {
+                continue;
+            }
+            execs += 1;
+            let mut attempt = colored.clone();
+            for byte in &mut attempt[range.clone()] {
+                *byte = recolor(state.rand_mut(), *byte);
+            }
+            let trace = self.trace(fuzzer, executor, state, manager, &wrap(&attempt), false)?;
+            if trace.is_some_and(|trace| trace.path == orig.path) {
+                colored = attempt;
+                tainted[range].fill(true);
+            } else if range.len() > 1 {
+                let mid = range.start + range.len() / 2;
+                pending.push(range.start..mid);
+                pending.push(mid..range.end);
+            }
+        }
+
+        // Pair the comparisons up, which only works if the colorized run made the same ones
+        let colored_cmps = if tainted.contains(&true) {
+            self.trace(fuzzer, executor, state, manager, &wrap(&colored), true)?
+                .filter(|trace| trace.path == orig.path && trace.cmps.len() == orig.cmps.len())
+                .map(|trace| trace.cmps)
+        } else {
+            None
+        };
+        let paired = colored_cmps.is_some();
+        let input = Colorized {
+            data,
+            colored: paired.then_some((colored, tainted)),
+        };
+
+        let mut pairs = HashSet::new();
+        let mut edits = Edits::default();
+        for (idx, cmp) in orig.cmps.iter().enumerate() {
+            let Some(operands) = Operands::of(cmp) else {
+                continue;
+            };
+            let colored = colored_cmps
+                .as_ref()
+                .and_then(|cmps| Operands::of(&cmps[idx]));
+            if pairs.len() >= MAX_CMPS || !pairs.insert((operands.clone(), colored.clone())) {
+                continue;
+            }
+            match (operands, colored) {
+                (Operands::Int { v0, v1, size }, colored) => {
+                    let colored = match colored {
+                        Some(Operands::Int { v0, v1, .. }) => Some((v0, v1)),
+                        _ => None,
+                    };
+                    int_edits(&input, (v0, v1, size), colored, &mut edits);
+                }
+                (Operands::Bytes { v0, v1 }, colored) => {
+                    let colored = match &colored {
+                        Some(Operands::Bytes { v0, v1 }) => Some((v0.as_slice(), v1.as_slice())),
+                        _ => None,
+                    };
+                    bytes_edits(&input, (&v0, &v1), colored, &mut edits);
+                }
+            }
+        }
+        log::debug!(
+            "Redqueen on #{id}: {execs} colorization runs, {} comparisons, {} replacements",
+            pairs.len(),
+            edits.edits.len()
+        );
+
+        for (pos, len, bytes) in edits.edits {
+            let mut candidate = data.to_vec();
+            candidate.splice(pos..pos + len, bytes);
+            let candidate = wrap(&candidate);
+            if candidate.target_bytes().as_slice().len() > state.max_size() {
+                continue;
+            }
+            fuzzer.evaluate_input(state, executor, manager, candidate)?;
+        }
+
+        if let Some(list) = tracing_cmps {
+            state.metadata_mut::<CmpValuesMetadata>()?.list = list;
+        }
+        Ok(())
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::{HashSet, VecDeque};
+
+use libafl::{
+    corpus::Corpus,
+    events::{CustomBufEventResult, Event, EventFirer},
+    fuzzer::ExecutesInput,
+    inputs::{BytesInput, HasTargetBytes},
+    stages::Stage,
+    state::HasSolutions,
+    Error, HasMetadata,
+};
+use libafl_bolts::{hash_std, AsSlice};
+use serde::{Deserialize, Serialize};
+
+use crate::instance::ClientState;
+
+/// The tag of the events carrying solutions to the other clients
+pub const SHARED_OBJECTIVE_TAG: &str = "shared_objective";
+
+/// Received solutions are kept at most this many at a time until they are re-executed
+const MAX_PENDING_OBJECTIVES: usize = 64;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SharedObjectivesMeta {
+    /// How many solutions have already been looked at
+    next_solution_idx: usize,
+    /// Content hashes of the solutions other clients sent us, they are never sent on
+    received: HashSet<u64>,
+    /// Received solutions waiting to be re-executed
+    pending: VecDeque<Vec<u8>>,
+}
+
+libafl_bolts::impl_serdeany!(SharedObjectivesMeta);
+
+/// Broadcasts the solutions this client found on its own, once, so every other client (also
+/// on remote nodes, via the broker-to-broker connection) re-executes them and stores them in
+/// its own `crashes` directory. Solutions stored because another client sent them are not
+/// broadcast again.
+///
+/// Re-executes one received solution per iteration: if it crashes here as well, the
+/// objectives store it from within the crash handler.
+#[derive(Debug, Default)]
+pub struct ShareObjectivesStage;
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for ShareObjectivesStage
+where
+    EM: EventFirer<BytesInput, ClientState>,
+    Z: ExecutesInput<E, EM, BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let count = state.solutions().count();
+        let meta = state.metadata_or_insert_with(SharedObjectivesMeta::default);
+        let mut next = meta.next_solution_idx;
+        let received = meta.pending.pop_front();
+
+        while next < count {
+            let id = state.solutions().nth(next);
+            let input = state.solutions().cloned_input_for_id(id)?;
+            let bytes = input.target_bytes().as_slice().to_vec();
+            next += 1;
+            if state
+                .metadata::<SharedObjectivesMeta>()?
+                .received
+                .contains(&hash_std(&bytes))
+            {
+                continue;
+            }
+            log::info!("Sharing objective #{} with other nodes", next - 1);
+            manager.fire(
+                state,
+                Event::CustomBuf {
+                    buf: bytes,
+                    tag: SHARED_OBJECTIVE_TAG.to_string(),
+                },
+            )?;
+        }
+        state
+            .metadata_mut::<SharedObjectivesMeta>()?
+            .next_solution_idx = next;
+
+        if let Some(bytes) = received {
+            log::debug!("Re-executing a received objective");
+            fuzzer.execute_input(state, executor, manager, &BytesInput::new(bytes))?;
+        }
+        Ok(())
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        // The received solution is already popped, a restart continues with the next one
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Queues a solution another client broadcast, for the custom buf handlers of the event
+/// manager
+pub fn receive_objective(
+    state: &mut ClientState,
+    tag: &str,
+    buf: &[u8],
+) -> Result<CustomBufEventResult, Error> {
+    if tag != SHARED_OBJECTIVE_TAG {
+        return Ok(CustomBufEventResult::Next);
+    }
+    let meta = state.metadata_or_insert_with(SharedObjectivesMeta::default);
+    if meta.received.insert(hash_std(buf)) && meta.pending.len() < MAX_PENDING_OBJECTIVES {
+        meta.pending.push_back(buf.to_vec());
+    }
+    Ok(CustomBufEventResult::Handled)
+}
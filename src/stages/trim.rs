@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use libafl::{
+    corpus::{Corpus, CorpusId, Testcase},
+    executors::ExitKind,
+    fuzzer::{ExecutesInput, HasScheduler},
+    inputs::{BytesInput, HasTargetBytes},
+    schedulers::RemovableScheduler,
+    stages::Stage,
+    state::{HasCorpus, HasCurrentCorpusId},
+    Error, HasMetadata,
+};
+use libafl_bolts::AsSlice;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    instance::ClientState, modules::fault_schedule::FaultSchedule, stages::redqueen::path_hash,
+};
+
+/// Smaller chunks are not worth the executions
+const MIN_REMOVE: usize = 4;
+
+/// Executions trimming may spend on one input
+const MAX_TRIM_EXECS: usize = 1024;
+
+/// The corpus entries the stage already trimmed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrimMeta {
+    done: HashSet<CorpusId>,
+}
+
+libafl_bolts::impl_serdeany!(TrimMeta);
+
+/// Trims each corpus entry once, like `afl-fuzz` does: chunks from 1/16 down to 1/1024 of
+/// the input are removed as long as the execution keeps its path. A shorter input replaces
+/// the entry, so the mutations after us work on less bytes. The fault schedule of the input
+/// is kept as it is.
+#[derive(Debug, Default)]
+pub struct TrimStage;
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for TrimStage
+where
+    Z: ExecutesInput<E, EM, BytesInput, ClientState> + HasScheduler<BytesInput, ClientState>,
+    Z::Scheduler: RemovableScheduler<BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(id) = state.current_corpus_id()? else {
+            return Ok(());
+        };
+        if !state
+            .metadata_or_insert_with(TrimMeta::default)
+            .done
+            .insert(id)
+        {
+            return Ok(());
+        }
+
+        let input = state.corpus().cloned_input_for_id(id)?;
+        let bytes = input.target_bytes();
+        let (orig, schedule) = FaultSchedule::split(bytes.as_slice());
+        let has_schedule = orig.len() != bytes.len();
+        let wrap = |data: &[u8]| {
+            BytesInput::new(if has_schedule {
+                schedule.join(data)
+            } else {
+                data.to_vec()
+            })
+        };
+        if orig.len() < 2 * MIN_REMOVE {
+            return Ok(());
+        }
+
+        if fuzzer.execute_input(state, executor, manager, &input)? != ExitKind::Ok {
+            return Ok(());
+        }
+        let path = path_hash();
+
+        let mut data = orig.to_vec();
+        let mut remove = (orig.len().next_power_of_two() / 16).max(MIN_REMOVE);
+        let min_remove = (orig.len().next_power_of_two() / 1024).max(MIN_REMOVE);
+        let mut execs = 0;
+        while remove >= min_remove && execs < MAX_TRIM_EXECS {
+            let mut pos = 0;
+            while pos < data.len() && execs < MAX_TRIM_EXECS {
+                let end = (pos + remove).min(data.len());
+                let mut candidate = data.clone();
+                candidate.drain(pos..end);
+                execs += 1;
+                let exit_kind =
+                    fuzzer.execute_input(state, executor, manager, &wrap(&candidate))?;
+                if exit_kind == ExitKind::Ok && path_hash() == path {
+                    data = candidate;
+                } else {
+                    pos += remove;
+                }
+            }
+            remove /= 2;
+        }
+        log::debug!(
+            "Trimmed #{id} from {} to {} bytes in {execs} executions",
+            orig.len(),
+            data.len()
+        );
+        if data.len() == orig.len() {
+            return Ok(());
+        }
+
+        // A fresh testcase, the length the scheduler cached is that of the old input
+        let prev = state.corpus().get(id)?.borrow().clone();
+        let mut testcase = Testcase::new(wrap(&data));
+        *testcase.metadata_map_mut() = prev.metadata_map().clone();
+        *testcase.exec_time_mut() = *prev.exec_time();
+        state.corpus_mut().replace(id, testcase)?;
+        fuzzer.scheduler_mut().on_replace(state, id, &prev)
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
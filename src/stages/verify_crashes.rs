@@ -0,0 +1,50 @@
+use libafl::{fuzzer::ExecutesInput, inputs::BytesInput, stages::Stage, Error, HasMetadata};
+
+use crate::{instance::ClientState, modules::checksum_patch::UnpatchedCrashesMeta};
+
+/// Re-executes one crash with fixed checksums per iteration, with the checksum checks of the
+/// target restored by the `ChecksumPatchModule`.
+///
+/// If it crashes again, the objectives store it from within the crash handler, so reaching the
+/// end of [`Stage::perform`] means the crash depended on the patches and is dropped.
+#[derive(Debug, Default)]
+pub struct VerifyCrashesStage;
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for VerifyCrashesStage
+where
+    Z: ExecutesInput<E, EM, BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some((bytes, hits)) = state
+            .metadata_mut::<UnpatchedCrashesMeta>()
+            .ok()
+            .and_then(UnpatchedCrashesMeta::pop_pending)
+        else {
+            return Ok(());
+        };
+
+        state
+            .metadata_mut::<UnpatchedCrashesMeta>()?
+            .set_verifying(Some(hits));
+        let res = fuzzer.execute_input(state, executor, manager, &BytesInput::new(bytes));
+        state.metadata_mut::<UnpatchedCrashesMeta>()?.set_verifying(None);
+
+        log::info!("Crash with fixed checksums did not reproduce on the original checks, dropped");
+        res.map(|_| ())
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        // The pending crash is already popped, a restart continues with the next one
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
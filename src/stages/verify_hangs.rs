@@ -0,0 +1,65 @@
+use core::time::Duration;
+
+use libafl::{
+    executors::HasTimeout, fuzzer::ExecutesInput, inputs::BytesInput, stages::Stage, Error,
+    HasMetadata,
+};
+
+use crate::{feedbacks::hang::HangsMeta, instance::ClientState};
+
+/// Re-executes one timed out input per iteration with the (longer) hang timeout.
+///
+/// If it times out again, the `HangFeedback` stores it from within the timeout handler,
+/// so reaching the end of [`Stage::perform`] means the timeout did not reproduce.
+#[derive(Debug)]
+pub struct VerifyHangsStage {
+    timeout: Duration,
+}
+
+impl VerifyHangsStage {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl<E, EM, Z> Stage<E, EM, ClientState, Z> for VerifyHangsStage
+where
+    E: HasTimeout,
+    Z: ExecutesInput<E, EM, BytesInput, ClientState>,
+{
+    fn perform(
+        &mut self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut ClientState,
+        manager: &mut EM,
+    ) -> Result<(), Error> {
+        let Some(bytes) = state
+            .metadata_mut::<HangsMeta>()
+            .ok()
+            .and_then(HangsMeta::pop_pending)
+        else {
+            return Ok(());
+        };
+
+        state.metadata_mut::<HangsMeta>()?.set_verifying(true);
+
+        let timeout = executor.timeout();
+        executor.set_timeout(self.timeout);
+        let res = fuzzer.execute_input(state, executor, manager, &BytesInput::new(bytes));
+        executor.set_timeout(timeout);
+
+        state.metadata_mut::<HangsMeta>()?.set_verifying(false);
+        log::debug!("Timeout did not reproduce within {:?}", self.timeout);
+        res.map(|_| ())
+    }
+
+    fn should_restart(&mut self, _state: &mut ClientState) -> Result<bool, Error> {
+        // The pending hang is already popped, a restart continues with the next one
+        Ok(true)
+    }
+
+    fn clear_progress(&mut self, _state: &mut ClientState) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! An `afl-tmin` like testcase minimizer.
+//!
+//! Every candidate runs in a forked child of the initialized emulator, so the
+//! snapshot and the module stack behave exactly like during fuzzing and a
+//! crashing candidate only takes down the child. The QEMU crash hook can't return
+//! to the executor, so the snapshot reset alone can't survive a crash.
+use std::{collections::BTreeSet, ptr};
+
+use clap::ValueEnum;
+use libafl::{
+    executors::{ExitKind, HasObservers},
+    fuzzer::ExecutesInput,
+    inputs::BytesInput,
+    observers::MapObserver,
+    Error,
+};
+use libafl_bolts::{
+    hash_std,
+    tuples::{Handle, MatchName},
+};
+use libafl_qemu::{GuestReg, Qemu, Regs};
+use nix::{
+    sys::wait::waitpid,
+    unistd::{fork, ForkResult},
+};
+
+use crate::modules::crash_info::backtrace;
+
+/// The byte blocks are replaced with, same as `afl-tmin`
+const NORMALIZED_BYTE: u8 = b'0';
+
+/// Rounds of block deletion / normalization before giving up on further progress
+const MAX_ROUNDS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TminMode {
+    /// Keep the fault signal, PC and stack hash of the original crash
+    Crash,
+    /// Keep the (hitcount classified) edge coverage of the original input
+    Coverage,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    crashed: bool,
+    completed: bool,
+    signal: i32,
+    pc: GuestReg,
+    /// Of the return addresses of the crash, see [`crate::modules::crash_info::backtrace`]
+    stack_hash: u64,
+    coverage: u64,
+}
+
+/// A [`Signature`] living in a shared anonymous mapping, written by the forked child
+#[derive(Clone, Copy, Debug)]
+pub struct SignatureSlot {
+    ptr: *mut Signature,
+}
+
+impl SignatureSlot {
+    pub fn new() -> Result<Self, Error> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size_of::<Signature>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::unknown("Failed to map the tmin signature slot"));
+        }
+        Ok(Self {
+            ptr: ptr.cast::<Signature>(),
+        })
+    }
+
+    fn get(&self) -> Signature {
+        unsafe { ptr::read_volatile(self.ptr) }
+    }
+
+    fn set(&self, signature: Signature) {
+        unsafe { ptr::write_volatile(self.ptr, signature) };
+    }
+
+    /// Called from the QEMU crash hook inside the child: record the fault and
+    /// leave before the executor's crash handler stores the candidate as a solution.
+    pub fn on_crash(&self, qemu: Qemu, signal: i32) -> ! {
+        let pc: GuestReg = qemu.read_reg(Regs::Pc).unwrap_or_default();
+        let frames = backtrace(qemu)
+            .iter()
+            .flat_map(|addr| addr.to_le_bytes())
+            .collect::<Vec<u8>>();
+        self.set(Signature {
+            crashed: true,
+            signal,
+            pc,
+            stack_hash: hash_std(&frames),
+            ..Signature::default()
+        });
+        unsafe { libc::_exit(128 + signal) }
+    }
+}
+
+pub struct TestcaseMinimizer<C> {
+    mode: TminMode,
+    map_observer: Handle<C>,
+    slot: SignatureSlot,
+}
+
+impl<C> TestcaseMinimizer<C> {
+    pub fn new(mode: TminMode, map_observer: Handle<C>, slot: SignatureSlot) -> Self {
+        Self {
+            mode,
+            map_observer,
+            slot,
+        }
+    }
+
+    fn signature<E, EM, O, S, Z>(
+        &self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        mgr: &mut EM,
+        bytes: &[u8],
+    ) -> Result<Signature, Error>
+    where
+        C: AsRef<O>,
+        O: MapObserver,
+        E: HasObservers,
+        E::Observers: MatchName,
+        Z: ExecutesInput<E, EM, BytesInput, S>,
+    {
+        self.slot.set(Signature::default());
+
+        match unsafe { fork() }? {
+            ForkResult::Child => {
+                let input = BytesInput::new(bytes.to_vec());
+                if let Ok(ExitKind::Ok) = fuzzer.execute_input(state, executor, mgr, &input) {
+                    let map = executor.observers()[&self.map_observer].as_ref();
+                    self.slot.set(Signature {
+                        completed: true,
+                        coverage: map.hash_simple(),
+                        ..Signature::default()
+                    });
+                }
+                unsafe { libc::_exit(0) }
+            }
+            ForkResult::Parent { child } => {
+                waitpid(child, None)?;
+                Ok(self.slot.get())
+            }
+        }
+    }
+
+    fn preserves(&self, base: &Signature, candidate: &Signature) -> bool {
+        match self.mode {
+            TminMode::Crash => {
+                candidate.crashed
+                    && candidate.signal == base.signal
+                    && candidate.pc == base.pc
+                    && candidate.stack_hash == base.stack_hash
+            }
+            TminMode::Coverage => candidate.completed && candidate.coverage == base.coverage,
+        }
+    }
+
+    /// Shrink `input` by block deletion and byte normalization while the
+    /// signature selected by the [`TminMode`] stays the same.
+    pub fn minimize<E, EM, O, S, Z>(
+        &self,
+        fuzzer: &mut Z,
+        executor: &mut E,
+        state: &mut S,
+        mgr: &mut EM,
+        input: Vec<u8>,
+    ) -> Result<Vec<u8>, Error>
+    where
+        C: AsRef<O>,
+        O: MapObserver,
+        E: HasObservers,
+        E::Observers: MatchName,
+        Z: ExecutesInput<E, EM, BytesInput, S>,
+    {
+        let base = self.signature(fuzzer, executor, state, mgr, &input)?;
+        let valid = match self.mode {
+            TminMode::Crash => base.crashed,
+            TminMode::Coverage => base.completed,
+        };
+        if !valid {
+            return Err(Error::illegal_argument(format!(
+                "The input does not produce a {:?} signature: {base:?}",
+                self.mode
+            )));
+        }
+        log::info!("tmin: base signature {base:?}, {} bytes", input.len());
+
+        let mut execs = 1_usize;
+        let mut current = input;
+
+        for round in 0..MAX_ROUNDS {
+            let len_before = current.len();
+            let mut changed = false;
+
+            // Block deletion, from len / 16 down to single bytes
+            let mut block = (current.len() / 16).next_power_of_two().max(1);
+            loop {
+                let mut pos = 0;
+                while pos < current.len() {
+                    let end = (pos + block).min(current.len());
+                    let mut candidate = current[..pos].to_vec();
+                    candidate.extend_from_slice(&current[end..]);
+
+                    execs += 1;
+                    let signature = self.signature(fuzzer, executor, state, mgr, &candidate)?;
+                    if self.preserves(&base, &signature) {
+                        current = candidate;
+                        changed = true;
+                    } else {
+                        pos += block;
+                    }
+                }
+                if block == 1 {
+                    break;
+                }
+                block /= 2;
+            }
+
+            // Alphabet normalization: replace every occurrence of a byte value at once
+            let alphabet = current
+                .iter()
+                .copied()
+                .filter(|b| *b != NORMALIZED_BYTE)
+                .collect::<BTreeSet<u8>>();
+            for value in alphabet {
+                let candidate = current
+                    .iter()
+                    .map(|b| if *b == value { NORMALIZED_BYTE } else { *b })
+                    .collect::<Vec<u8>>();
+
+                execs += 1;
+                let signature = self.signature(fuzzer, executor, state, mgr, &candidate)?;
+                if self.preserves(&base, &signature) {
+                    current = candidate;
+                    changed = true;
+                }
+            }
+
+            // Byte normalization
+            for idx in 0..current.len() {
+                if current[idx] == NORMALIZED_BYTE {
+                    continue;
+                }
+                let mut candidate = current.clone();
+                candidate[idx] = NORMALIZED_BYTE;
+
+                execs += 1;
+                let signature = self.signature(fuzzer, executor, state, mgr, &candidate)?;
+                if self.preserves(&base, &signature) {
+                    current = candidate;
+                    changed = true;
+                }
+            }
+
+            log::info!(
+                "tmin: round {round}: {len_before} -> {} bytes ({execs} execs)",
+                current.len()
+            );
+            if !changed {
+                break;
+            }
+        }
+
+        Ok(current)
+    }
+}
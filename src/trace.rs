@@ -0,0 +1,123 @@
+//! Per-execution event tracing.
+//!
+//! Log output is filtered per component through the `log` targets used on the hot path
+//! (`harness`, `injector`, `register`, `feedback`), e.g. `--log-filter info,injector=trace`.
+//! On top of that, the last N execution events can be kept in a ring buffer that is dumped
+//! into the metadata of each crash. Both cost a single relaxed load while disabled.
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static RING_ENABLED: AtomicBool = AtomicBool::new(false);
+static RING: Mutex<ExecRing> = Mutex::new(ExecRing::new());
+
+/// Record an execution event in the ring buffer, if it is enabled
+macro_rules! exec_event {
+    ($component:literal, $what:literal) => {
+        $crate::trace::exec_event!($component, $what, 0)
+    };
+    ($component:literal, $what:literal, $value:expr) => {
+        if $crate::trace::ring_enabled() {
+            $crate::trace::record($component, $what, $value as u64);
+        }
+    };
+}
+pub(crate) use exec_event;
+
+#[derive(Debug, Clone, Copy)]
+struct ExecEvent {
+    exec: u64,
+    component: &'static str,
+    what: &'static str,
+    value: u64,
+}
+
+#[derive(Debug)]
+struct ExecRing {
+    events: Vec<ExecEvent>,
+    capacity: usize,
+    next: usize,
+    exec: u64,
+}
+
+impl ExecRing {
+    const fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            capacity: 0,
+            next: 0,
+            exec: 0,
+        }
+    }
+
+    fn push(&mut self, event: ExecEvent) {
+        if self.events.len() < self.capacity {
+            self.events.push(event);
+        } else {
+            self.events[self.next] = event;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// Oldest event first
+    fn iter(&self) -> impl Iterator<Item = &ExecEvent> {
+        let (newer, older) = if self.events.len() < self.capacity {
+            (&self.events[..], &self.events[..0])
+        } else {
+            self.events.split_at(self.next)
+        };
+        older.iter().chain(newer.iter())
+    }
+}
+
+/// Keep the last `capacity` execution events, 0 disables the ring buffer
+pub fn init_ring(capacity: usize) {
+    if capacity == 0 {
+        return;
+    }
+    if let Ok(mut ring) = RING.lock() {
+        ring.events = Vec::with_capacity(capacity);
+        ring.capacity = capacity;
+    }
+    RING_ENABLED.store(true, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn ring_enabled() -> bool {
+    RING_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Mark the start of a new execution, events are tagged with the execution counter
+pub fn next_exec() {
+    if !ring_enabled() {
+        return;
+    }
+    if let Ok(mut ring) = RING.lock() {
+        ring.exec += 1;
+    }
+}
+
+pub fn record(component: &'static str, what: &'static str, value: u64) {
+    if let Ok(mut ring) = RING.lock() {
+        let exec = ring.exec;
+        ring.push(ExecEvent {
+            exec,
+            component,
+            what,
+            value,
+        });
+    }
+}
+
+/// The recorded events, oldest first, formatted for the crash metadata
+pub fn dump() -> Vec<String> {
+    if !ring_enabled() {
+        return Vec::new();
+    }
+    RING.lock()
+        .map(|ring| {
+            ring.iter()
+                .map(|e| format!("#{} [{}] {} {:#x}", e.exec, e.component, e.what, e.value))
+                .collect()
+        })
+        .unwrap_or_default()
+}